@@ -2,8 +2,11 @@
 use cfg_if::cfg_if;
 use log::info;
 
-use std::fs::File;
+use std::fs;
+use std::path::Path;
 use super::domain::{Setup, TodoError, TodoErrorType, TodoResponse};
+#[cfg(test)]
+use super::paths::Paths;
 
 
 cfg_if! {
@@ -14,10 +17,13 @@ cfg_if! {
     }
 }
 
-pub const CONFIG_FILE: &str = "app.conf";
+/// Name of the config file in the config directory
+pub const CONFIG_FILE: &str = "config.toml";
+/// Name of the `key=value` config of earlier versions, read while there is no TOML one
+pub const LEGACY_CONFIG_FILE: &str = "app.conf";
 
 fn initialize_setup(settings: &Settings) -> Result<TodoResponse, TodoError> {
-    let result_error = initialize_config_file();
+    let result_error = initialize_config_file(&settings.get_config_file());
     if result_error.is_err() {
         result_error
     } else {
@@ -35,15 +41,17 @@ fn initialize_setup(settings: &Settings) -> Result<TodoResponse, TodoError> {
     }
 }
 
-fn initialize_config_file() -> Result<TodoResponse, TodoError> {
-    if File::open(CONFIG_FILE).is_err() {
-        match File::create(CONFIG_FILE) {
-            Ok(_) => Ok(TodoResponse::Done),
-            _ => Err(TodoError::build(TodoErrorType::UnableToInitialize)),
-        }
-    } else {
+/// Creates the directory of the config, the config itself is written with the settings
+fn initialize_config_file(path: &Path) -> Result<TodoResponse, TodoError> {
+    if path.exists() {
         info!("Config initialized");
-        Ok(TodoResponse::Done)
+        return Ok(TodoResponse::Done);
+    }
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => fs::create_dir_all(dir)
+            .map(|_| TodoResponse::Done)
+            .map_err(|why| TodoError::io(TodoErrorType::UnableToInitialize, why)),
+        None => Ok(TodoResponse::Done),
     }
 }
 
@@ -57,12 +65,21 @@ pub fn config_router(configuration: &Settings, setup: Setup) -> Result<TodoRespo
                 Err(TodoError::build(TodoErrorType::TestFailed))
             }
         }
+        Setup::Migrate | Setup::MigrationStatus if !configuration.is_config_available() => {
+            Err(TodoError::build(TodoErrorType::InitNotAvailable))
+        }
+        Setup::Migrate => configuration.migrate_db(),
+        Setup::Configure(argument) => initialize_config_file(&configuration.get_config_file())
+            .and_then(|_| configuration.write_custom_config(&argument)),
+        Setup::MigrationStatus => configuration.schema_status(),
     }
 }
 
 #[test]
 fn initialize_setup_test() {
     let mut mock = Settings::new();
+    mock.expect_get_config_file()
+        .returning(|| std::env::temp_dir().join("xcon-init-test.conf"));
     mock.expect_write_default_config()
         .returning(|| Ok(TodoResponse::Done));
     mock.expect_initalize_db()
@@ -75,7 +92,7 @@ fn initialize_setup_test() {
 fn load_config_test() {
     let settings_ctx = Settings::load_config_context();
 
-    let _settings = settings_ctx.expect().returning(|| Ok(Settings::new()));
-    let response = Settings::load_config();
+    let _settings = settings_ctx.expect().returning(|_| Ok(Settings::new()));
+    let response = Settings::load_config(Paths::resolve(None));
     assert!(matches!(response, _settings));
 }
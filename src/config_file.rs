@@ -0,0 +1,980 @@
+//! Contents of the configuration file, a TOML document like:
+//!
+//! ```toml
+//! editor = "vim"
+//! confirm = "always"
+//!
+//! [database]
+//! name = "todo"
+//! backend = "sqlite"
+//!
+//! [output]
+//! format = "table"
+//! date_format = "%Y-%m-%d"
+//! color = "auto"
+//!
+//! [urgency]
+//! due = 12.0
+//!
+//! [aliases]
+//! ls = "list --format plain"
+//!
+//! [templates]
+//! short = "{id} {title:truncate(30)}"
+//! ```
+//!
+//! Every key is optional. Configurations written by earlier versions as `key=value`
+//! lines in `app.conf` are still read.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+use crate::dates::check_date_format;
+use crate::domain::{TodoError, TodoErrorType, Urgency};
+use crate::lock::FileLock;
+use crate::output::Format;
+use crate::paths::Paths;
+use crate::store::Backend;
+use crate::template::Template;
+
+const DEFAULT_DB_NAME: &str = "todo";
+const LEGACY_TEMPLATE_PREFIX: &str = "template.";
+const LEGACY_URGENCY_PREFIX: &str = "urgency.";
+/// Keys left out of the config until they are set
+const OPTIONAL_KEYS: [&str; 3] = ["editor", "output.format", "output.date_format"];
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Command opening todo for editing, `$VISUAL` or `$EDITOR` when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+    pub confirm: Confirm,
+    pub database: Database,
+    pub output: Output,
+    pub urgency: Urgency,
+    /// Commands run in place of the alias, `xcon ls` runs `xcon list --format plain`
+    /// with `ls = "list --format plain"`. Aliases never shadow a command
+    pub aliases: BTreeMap<String, String>,
+    /// Named list templates, used with `list --template <name>`
+    pub templates: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Database {
+    /// Database used unless `--db` names another one
+    pub name: String,
+    pub backend: Backend,
+}
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_DB_NAME.to_owned(),
+            backend: Backend::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Format used unless `--format` is given, a table at a terminal and ndjson otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    /// strftime format of the due dates in tables and plain lists, relative when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    pub color: Color,
+}
+
+/// When the output is colored
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// At a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+impl Color {
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            Color::Auto => terminal && env::var_os("NO_COLOR").is_none(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// Which removals are confirmed before anything is deleted
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Confirm {
+    #[default]
+    Always,
+    /// Only removals of several or all records
+    Bulk,
+    Never,
+}
+impl Confirm {
+    pub fn required(self, bulk: bool) -> bool {
+        match self {
+            Confirm::Always => true,
+            Confirm::Bulk => bulk,
+            Confirm::Never => false,
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads the TOML config, or the legacy `key=value` one when only that exists.
+    /// A missing config is reported as not initialized
+    pub fn load(paths: &Paths) -> Result<Self, TodoError> {
+        let path = paths.config_source();
+        let legacy = path != paths.config;
+        let text = fs::read_to_string(path).map_err(|why| {
+            if why.kind() == ErrorKind::NotFound {
+                TodoError::caused_by(TodoErrorType::InitNotAvailable, why)
+            } else {
+                TodoError::io(TodoErrorType::StorageFailure, why)
+            }
+        })?;
+        let config = if legacy {
+            Self::from_legacy(&text)
+        } else {
+            Self::from_toml(&text)
+        };
+        config.map_err(|why| why.context(format!("Invalid config {}", path.display())))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, TodoError> {
+        let config: Self = toml::from_str(text).map_err(|why| toml_error(text, why))?;
+        config.validate(text)?;
+        Ok(config)
+    }
+
+    /// Reads the `key=value` lines written by earlier versions, keys it does not
+    /// know are skipped
+    pub fn from_legacy(text: &str) -> Result<Self, TodoError> {
+        let mut config = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| {
+                TodoError::with_message(
+                    TodoErrorType::InvalidInput,
+                    format!("{} at line {}", message, number + 1),
+                )
+            };
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| invalid(format!("expected key=value, found `{}`", line)))?;
+            match key {
+                "db" => config.database.name = value.to_owned(),
+                "backend" => {
+                    config.database.backend = value
+                        .parse()
+                        .map_err(|why| invalid(format!("{} for key `{}`", why, key)))?
+                }
+                _ if key.starts_with(LEGACY_URGENCY_PREFIX) => {
+                    let weight = value
+                        .parse::<f64>()
+                        .map_err(|_| invalid(format!("expected a number for key `{}`", key)))?;
+                    match &key[LEGACY_URGENCY_PREFIX.len()..] {
+                        "priority" => config.urgency.priority = weight,
+                        "due" => config.urgency.due = weight,
+                        "age" => config.urgency.age = weight,
+                        "tags" => config.urgency.tags = weight,
+                        _ => warn!("Skipping unknown key `{}` at line {}", key, number + 1),
+                    }
+                }
+                _ if key.starts_with(LEGACY_TEMPLATE_PREFIX) => {
+                    let name = &key[LEGACY_TEMPLATE_PREFIX.len()..];
+                    config.templates.insert(name.to_owned(), value.to_owned());
+                }
+                _ => warn!("Skipping unknown key `{}` at line {}", key, number + 1),
+            }
+        }
+        config.validate(text)?;
+        Ok(config)
+    }
+
+    /// Keys of a legacy config that xcon never wrote, or the line when it is not a
+    /// `key=value` one
+    pub fn foreign_legacy_keys(text: &str) -> Vec<&str> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split_once('=').map_or(line, |(key, _)| key.trim()))
+            .filter(|key| !is_legacy_key(key))
+            .collect()
+    }
+
+    /// Checks what the types alone do not, errors name the key and its line in the text
+    fn validate(&self, text: &str) -> Result<(), TodoError> {
+        let invalid = |key: &str, message: String| {
+            let at = line_of(text, key)
+                .map(|line| format!(" at line {}", line))
+                .unwrap_or_default();
+            Err(TodoError::with_message(
+                TodoErrorType::InvalidInput,
+                format!("{} for key `{}`{}", message, key, at),
+            ))
+        };
+        if self.database.name.trim().is_empty() {
+            return invalid("database.name", "expected a database name".to_owned());
+        }
+        if let Some(format) = &self.output.date_format {
+            if let Err(why) = check_date_format(format) {
+                return invalid("output.date_format", why);
+            }
+        }
+        let weights = [
+            ("priority", self.urgency.priority),
+            ("due", self.urgency.due),
+            ("age", self.urgency.age),
+            ("tags", self.urgency.tags),
+        ];
+        for (factor, weight) in weights.iter() {
+            if !weight.is_finite() || *weight < 0.0 {
+                let key = format!("urgency.{}", factor);
+                return invalid(
+                    &key,
+                    format!("expected a positive weight, found {}", weight),
+                );
+            }
+        }
+        for (name, command) in &self.aliases {
+            let key = format!("aliases.{}", name);
+            if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+                return invalid(&key, "expected a single word alias".to_owned());
+            }
+            if command.trim().is_empty() {
+                return invalid(&key, "expected the command of the alias".to_owned());
+            }
+        }
+        for (name, template) in &self.templates {
+            if let Err(why) = template.parse::<Template>() {
+                return invalid(&format!("templates.{}", name), why);
+            }
+        }
+        Ok(())
+    }
+
+    /// Words of the command the alias stands for
+    pub fn alias(&self, name: &str) -> Option<Vec<String>> {
+        self.aliases
+            .get(name)
+            .map(|command| command.split_whitespace().map(str::to_owned).collect())
+    }
+
+    /// Every key with a value as a dotted key, set in the config file or by default
+    pub fn entries(&self, paths: &Paths) -> Vec<ConfigEntry> {
+        let defaults = flatten(&default_table());
+        let in_file = fs::read_to_string(&paths.config)
+            .ok()
+            .and_then(|text| toml::from_str::<Table>(&text).ok())
+            .map(|table| flatten(&table));
+        let values = match Value::try_from(self) {
+            Ok(Value::Table(table)) => flatten(&table),
+            _ => BTreeMap::new(),
+        };
+        values
+            .into_iter()
+            .map(|(key, value)| {
+                let written = match &in_file {
+                    Some(in_file) => in_file.contains_key(&key),
+                    None => defaults.get(&key) != Some(&value),
+                };
+                let origin = if written {
+                    Origin::File
+                } else {
+                    Origin::Default
+                };
+                ConfigEntry { key, value, origin }
+            })
+            .collect()
+    }
+}
+
+/// Where the value of a key comes from, from the lowest to the highest precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    Default,
+    File,
+    Flag,
+    /// Computed from other keys and the environment, listed but never set
+    Derived,
+}
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Origin::Default => "default",
+            Origin::File => "file",
+            Origin::Flag => "flag",
+            Origin::Derived => "derived",
+        };
+        f.pad(name)
+    }
+}
+
+/// Effective value of a dotted key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: Value,
+    pub origin: Origin,
+}
+impl ConfigEntry {
+    /// Value as given in the config, strings without their quotes
+    pub fn raw_value(&self) -> String {
+        match &self.value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        }
+    }
+}
+impl fmt::Display for ConfigEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.key, self.value)
+    }
+}
+
+/// Reads the value given on the command line for the key, as a number when its
+/// default is one and as a string otherwise
+pub fn parse_value(key: &str, text: &str) -> Result<Value, TodoError> {
+    match get(&default_table(), key) {
+        Some(Value::Float(_)) => {
+            text.trim().parse().map(Value::Float).map_err(|_| {
+                TodoError::invalid_input(format!("expected a number for key `{}`", key))
+            })
+        }
+        Some(Value::Table(_)) => Err(TodoError::invalid_input(format!(
+            "`{}` is a table, set one of its keys",
+            key
+        ))),
+        Some(_) => Ok(Value::from(text)),
+        None if is_optional_key(key) => Ok(Value::from(text)),
+        None => Err(TodoError::invalid_input(format!(
+            "Unknown config key `{}`",
+            key
+        ))),
+    }
+}
+
+/// Keys without a default, the named aliases and templates included
+pub fn is_optional_key(key: &str) -> bool {
+    let named = |table: &str| {
+        key.strip_prefix(table)
+            .and_then(|name| name.strip_prefix('.'))
+            .is_some_and(|name| !name.is_empty())
+    };
+    OPTIONAL_KEYS.contains(&key) || named("aliases") || named("templates")
+}
+
+fn default_table() -> Table {
+    match Value::try_from(ConfigFile::default()) {
+        Ok(Value::Table(table)) => table,
+        _ => Table::new(),
+    }
+}
+
+/// Values of the table by dotted key, empty tables left out
+fn flatten(table: &Table) -> BTreeMap<String, Value> {
+    let mut values = BTreeMap::new();
+    for (name, value) in table {
+        match value {
+            Value::Table(inner) => {
+                for (key, value) in flatten(inner) {
+                    values.insert(format!("{}.{}", name, key), value);
+                }
+            }
+            value => {
+                values.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    values
+}
+
+/// toml points errors in values at the start of their table, the key it names is
+/// looked up to report its own line
+fn toml_error(text: &str, why: toml::de::Error) -> TodoError {
+    let message = why.to_string();
+    let (reason, table) = match message.split_once(" for key `") {
+        Some((reason, rest)) => (reason, rest.split('`').next()),
+        None => (message.split(" at line ").next().unwrap_or_default(), None),
+    };
+    let field = reason
+        .strip_prefix("unknown field `")
+        .and_then(|field| field.split('`').next());
+    let key = match (table, field) {
+        (Some(table), Some(field)) => format!("{}.{}", table, field),
+        (Some(table), None) => table.to_owned(),
+        (None, Some(field)) => field.to_owned(),
+        (None, None) => return TodoError::with_message(TodoErrorType::InvalidInput, message),
+    };
+    let at = line_of(text, &key)
+        .or_else(|| table.and_then(|table| line_of(text, table)))
+        .map(|line| format!(" at line {}", line))
+        .unwrap_or_default();
+    TodoError::with_message(
+        TodoErrorType::InvalidInput,
+        format!("{} for key `{}`{}", reason, key, at),
+    )
+}
+
+fn is_legacy_key(key: &str) -> bool {
+    match key {
+        "db" | "backend" => true,
+        _ if key.starts_with(LEGACY_URGENCY_PREFIX) => {
+            ["priority", "due", "age", "tags"].contains(&&key[LEGACY_URGENCY_PREFIX.len()..])
+        }
+        _ => key.len() > LEGACY_TEMPLATE_PREFIX.len() && key.starts_with(LEGACY_TEMPLATE_PREFIX),
+    }
+}
+
+/// Line number of the key, given with its table like `output.format`, in a TOML or
+/// legacy config
+fn line_of(text: &str, key: &str) -> Option<usize> {
+    let mut table = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            table = line
+                .trim_matches(|c| c == '[' || c == ']')
+                .trim()
+                .to_owned();
+        } else if let Some((name, _)) = line.split_once('=') {
+            let name = name.trim().trim_matches('"');
+            let full = if table.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}.{}", table, name)
+            };
+            if full == key {
+                return Some(number + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Value of a key before and after a write, defaults included, `None` when the key
+/// is neither set nor has a default
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub previous: Option<Value>,
+    pub value: Option<Value>,
+}
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.previous, &self.value) {
+            (previous, Some(value)) if previous.as_ref() == Some(value) => {
+                write!(f, "{} = {} (unchanged)", self.key, value)
+            }
+            (Some(previous), Some(value)) => {
+                write!(f, "{} = {} (was {})", self.key, value, previous)
+            }
+            (None, Some(value)) => write!(f, "{} = {}", self.key, value),
+            (Some(previous), None) => write!(f, "{} unset (was {})", self.key, previous),
+            (None, None) => write!(f, "{} is not set", self.key),
+        }
+    }
+}
+
+/// Sets the dotted keys in the TOML config, or removes them back to their default
+/// when given no value, starting from what `base` sets apart from the defaults when
+/// there is no config yet. Only the lines of the changed keys are touched, comments
+/// and the layout of the others are kept. Concurrent commands take turns through a
+/// lock file next to the config, which is replaced in a single rename so it is never
+/// read half written
+pub fn write_changes(
+    path: &Path,
+    base: &ConfigFile,
+    changes: &[(&str, Option<Value>)],
+) -> Result<Vec<ConfigChange>, TodoError> {
+    let write_error = |why| TodoError::io(TodoErrorType::UnableToInitialize, why);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(write_error)?;
+    }
+    let _lock = FileLock::acquire(with_suffix(path, ".lock"))?;
+    let defaults = default_table();
+    let in_place = || {
+        TodoError::invalid_input(format!(
+            "Unable to change {} in place, edit it with `xcon config edit`",
+            path.display()
+        ))
+    };
+    let mut edited = BTreeMap::new();
+    let (mut text, mut table) = match fs::read_to_string(path) {
+        Ok(text) => {
+            ConfigFile::from_toml(&text)
+                .map_err(|why| why.context(format!("Invalid config {}", path.display())))?;
+            let table = toml::from_str::<Table>(&text)
+                .map_err(|why| TodoError::caused_by(TodoErrorType::InvalidInput, why))?;
+            (text, table)
+        }
+        Err(why) if why.kind() == ErrorKind::NotFound => {
+            let table = match Value::try_from(base) {
+                Ok(Value::Table(table)) => table,
+                _ => Table::new(),
+            };
+            let defaults = flatten(&defaults);
+            let mut text = String::new();
+            for (key, value) in flatten(&table) {
+                if defaults.get(&key) != Some(&value) {
+                    text = edit_text(&text, &key, Some(&value)).ok_or_else(in_place)?;
+                    edited.insert(key, Some(value));
+                }
+            }
+            (text, table)
+        }
+        Err(why) => return Err(write_error(why)),
+    };
+    let mut written = Vec::new();
+    for (key, value) in changes {
+        let default = get(&defaults, key);
+        let previous = set(&mut table, key, value.clone());
+        text = edit_text(&text, key, value.as_ref()).ok_or_else(in_place)?;
+        edited.insert((*key).to_owned(), value.clone());
+        written.push(ConfigChange {
+            key: (*key).to_owned(),
+            previous: previous.or_else(|| default.clone()),
+            value: value.clone().or(default),
+        });
+    }
+    // A key set inside an inline table is not found on its own line
+    let edits_applied = toml::from_str::<Table>(&text)
+        .is_ok_and(|table| edited.iter().all(|(key, value)| get(&table, key) == *value));
+    if !edits_applied {
+        return Err(in_place());
+    }
+    ConfigFile::from_toml(&text)?;
+    let temporary = with_suffix(path, ".tmp");
+    fs::write(&temporary, text)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(write_error)?;
+    Ok(written)
+}
+
+/// Key assigned on a line of a TOML text, given with its table, along with the
+/// number of lines its value spans and where its `=` is
+struct Assignment {
+    key: String,
+    table: String,
+    line: usize,
+    span: usize,
+    equals: usize,
+}
+
+/// Sets the dotted key in the TOML text, or removes its line when given no value,
+/// every other line is left as it is. A key that is not there yet goes after the
+/// last one of its table, or in a new table at the end, `None` when the table is
+/// set some other way, inline or through dotted keys
+fn edit_text(text: &str, key: &str, value: Option<&Value>) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    let (assignments, headers) = layout(&lines);
+    let (table, name) = key.split_once('.').unwrap_or(("", key));
+    match (assignments.iter().find(|entry| entry.key == key), value) {
+        (Some(entry), Some(value)) => {
+            let line = &lines[entry.line];
+            let comment = match entry.span {
+                1 => trailing_comment(&line[entry.equals + 1..]),
+                _ => "",
+            };
+            let assigned = format!("{} = {}{}", line[..entry.equals].trim_end(), value, comment);
+            lines.splice(entry.line..entry.line + entry.span, Some(assigned));
+        }
+        (Some(entry), None) => {
+            lines.drain(entry.line..entry.line + entry.span);
+            // The header goes with the last key of its table, unless comments are left
+            if let Some((_, header)) = headers.iter().find(|(header, _)| *header == entry.table) {
+                let next = lines[header + 1..]
+                    .iter()
+                    .position(|line| line.trim().starts_with('['))
+                    .map_or(lines.len(), |line| header + 1 + line);
+                if lines[header + 1..next]
+                    .iter()
+                    .all(|line| line.trim().is_empty())
+                {
+                    lines.drain(*header..next);
+                }
+            }
+            while lines.last().is_some_and(|line| line.trim().is_empty()) {
+                lines.pop();
+            }
+        }
+        (None, Some(value)) => {
+            let assigned = format!("{} = {}", toml_key(name), value);
+            let end = assignments
+                .iter()
+                .rev()
+                .find(|entry| entry.table == table)
+                .map(|entry| entry.line + entry.span);
+            let header = headers.iter().find(|(header, _)| header == table);
+            match (end, header) {
+                (Some(end), _) => lines.insert(end, assigned),
+                (None, Some((_, line))) => lines.insert(line + 1, assigned),
+                (None, None) if table.is_empty() => {
+                    let first = headers.first().map_or(lines.len(), |(_, line)| *line);
+                    if first < lines.len() {
+                        lines.insert(first, String::new());
+                    }
+                    lines.insert(first, assigned);
+                }
+                (None, None) => {
+                    let prefix = format!("{}.", table);
+                    if assignments
+                        .iter()
+                        .any(|entry| entry.key == table || entry.key.starts_with(&prefix))
+                    {
+                        return None;
+                    }
+                    if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                        lines.push(String::new());
+                    }
+                    lines.push(format!("[{}]", toml_key(table)));
+                    lines.push(assigned);
+                }
+            }
+        }
+        (None, None) => {}
+    }
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Some(text)
+}
+
+/// Keys assigned in the lines and the table headers with their line
+fn layout(lines: &[String]) -> (Vec<Assignment>, Vec<(String, usize)>) {
+    let mut assignments = Vec::new();
+    let mut headers = Vec::new();
+    let mut table = String::new();
+    let mut number = 0;
+    while number < lines.len() {
+        let line = &lines[number];
+        let trimmed = line.trim();
+        let equals = line.find('=').filter(|_| !trimmed.starts_with('#'));
+        if trimmed.starts_with('[') {
+            table = dotted(
+                trimmed
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or_default(),
+            );
+            headers.push((table.clone(), number));
+            number += 1;
+        } else if let Some(equals) = equals {
+            let name = dotted(&line[..equals]);
+            let span = value_span(&lines[number..], equals + 1);
+            assignments.push(Assignment {
+                key: match table.as_str() {
+                    "" => name,
+                    table => format!("{}.{}", table, name),
+                },
+                table: table.clone(),
+                line: number,
+                span,
+                equals,
+            });
+            number += span;
+        } else {
+            number += 1;
+        }
+    }
+    (assignments, headers)
+}
+
+/// Dotted key as written, the quotes around its parts removed
+fn dotted(key: &str) -> String {
+    key.split('.')
+        .map(|part| part.trim().trim_matches(['"', '\'']))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Key as written in TOML, quoted when it is not a bare one
+fn toml_key(name: &str) -> String {
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !name.is_empty() && name.chars().all(bare) {
+        name.to_owned()
+    } else {
+        Value::from(name).to_string()
+    }
+}
+
+/// Number of lines taken by the value starting at `start` of the first line, more
+/// than one for multi-line strings, arrays and inline tables
+fn value_span(lines: &[String], start: usize) -> usize {
+    let value = lines[0][start..].trim_start();
+    for quotes in ["\"\"\"", "\'\'\'"].iter() {
+        if let Some(rest) = value.strip_prefix(quotes) {
+            if rest.contains(quotes) {
+                return 1;
+            }
+            return lines[1..]
+                .iter()
+                .position(|line| line.contains(quotes))
+                .map_or(lines.len(), |line| line + 2);
+        }
+    }
+    if !value.starts_with(['[', '{']) {
+        return 1;
+    }
+    let mut depth = 0;
+    for (number, line) in lines.iter().enumerate() {
+        depth += scan(if number == 0 { value } else { line }).1;
+        if depth <= 0 {
+            return number + 1;
+        }
+    }
+    lines.len()
+}
+
+/// Comment after a value, with the spaces before it
+fn trailing_comment(value: &str) -> &str {
+    let end = scan(value).0;
+    let spaces = value[..end].len() - value[..end].trim_end().len();
+    &value[end - spaces..]
+}
+
+/// Where the comment of the value starts and how many more brackets it opens than
+/// it closes, strings skipped
+fn scan(value: &str) -> (usize, i32) {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    for (at, c) in value.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                '#' => return (at, depth),
+                _ => {}
+            },
+        }
+    }
+    (value.len(), depth)
+}
+
+/// Value of the dotted key in the table
+fn get(table: &Table, key: &str) -> Option<Value> {
+    match key.split_once('.') {
+        Some((name, rest)) => match table.get(name) {
+            Some(Value::Table(inner)) => get(inner, rest),
+            _ => None,
+        },
+        None => table.get(key).cloned(),
+    }
+}
+
+/// Sets or removes the dotted key and returns its previous value
+fn set(table: &mut Table, key: &str, value: Option<Value>) -> Option<Value> {
+    match (key.split_once('.'), value) {
+        (Some((name, rest)), Some(value)) => {
+            let entry = table
+                .entry(name.to_owned())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            match entry {
+                Value::Table(inner) => set(inner, rest, Some(value)),
+                _ => None,
+            }
+        }
+        (Some((name, rest)), None) => match table.get_mut(name) {
+            Some(Value::Table(inner)) => set(inner, rest, None),
+            _ => None,
+        },
+        (None, Some(value)) => table.insert(key.to_owned(), value),
+        (None, None) => table.remove(key),
+    }
+}
+
+/// Sibling of the file with the suffix appended to its name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[test]
+fn from_toml_test() {
+    let config = ConfigFile::from_toml(
+        "editor = \"nano\"\nconfirm = \"bulk\"\n\n[database]\nname = \"work\"\n\n[output]\nformat = \"plain\"\n\n[aliases]\nls = \"list --format plain\"\n",
+    )
+    .unwrap();
+    assert_eq!(config.editor.as_deref(), Some("nano"));
+    assert_eq!(config.confirm, Confirm::Bulk);
+    assert_eq!(config.database.name, "work");
+    assert_eq!(config.database.backend, Backend::Sqlite);
+    assert_eq!(config.output.format, Some(Format::Plain));
+    assert_eq!(config.urgency, Urgency::default());
+    assert_eq!(
+        config.alias("ls"),
+        Some(vec![
+            "list".to_owned(),
+            "--format".to_owned(),
+            "plain".to_owned()
+        ])
+    );
+
+    let message = |text: &str| ConfigFile::from_toml(text).err().unwrap().to_string();
+    let unknown = message("[output]\nformat = \"jsn\"\n");
+    assert!(unknown.contains("output.format") && unknown.contains("line 2"));
+    let misspelt = message("editor = \"vi\"\n\n[output]\ncolour = \"auto\"\n");
+    assert!(misspelt.contains("output.colour") && misspelt.contains("line 4"));
+    let invalid = message("[database]\nname = \"todo\"\n[output]\ndate_format = \"%Q\"\n");
+    assert!(invalid.contains("output.date_format") && invalid.contains("line 4"));
+    let negative = message("[urgency]\ndue = -1.0\n");
+    assert!(negative.contains("urgency.due") && negative.contains("line 2"));
+}
+
+#[test]
+fn from_legacy_test() {
+    let config =
+        ConfigFile::from_legacy("db=work \nbackend=json\nurgency.due=3\ntemplate.short={id}\n")
+            .unwrap();
+    assert_eq!(config.database.name, "work");
+    assert_eq!(config.database.backend, Backend::Json);
+    assert_eq!(config.urgency.due, 3.0);
+    assert_eq!(config.templates["short"], "{id}");
+
+    let malformed = ConfigFile::from_legacy("db=todo\njust text\n")
+        .err()
+        .unwrap();
+    assert!(malformed.to_string().contains("line 2"));
+    let weight = ConfigFile::from_legacy("urgency.age=old\n").err().unwrap();
+    assert!(weight.to_string().contains("urgency.age"));
+}
+
+#[test]
+fn write_changes_test() {
+    let path = env::temp_dir().join(format!("xcon-config-{}.toml", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let base = ConfigFile::from_legacy("urgency.due=3\n").unwrap();
+    write_changes(
+        &path,
+        &base,
+        &[("database.name", Some(Value::from("work")))],
+    )
+    .unwrap();
+    let changed = write_changes(
+        &path,
+        &base,
+        &[
+            ("output.format", Some(Value::from("csv"))),
+            ("database.name", None),
+        ],
+    )
+    .unwrap();
+    assert_eq!(changed[0].to_string(), "output.format = \"csv\"");
+    assert_eq!(
+        changed[1].to_string(),
+        "database.name = \"todo\" (was \"work\")"
+    );
+
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "[urgency]\ndue = 3.0\n\n[output]\nformat = \"csv\"\n"
+    );
+    let invalid = write_changes(&path, &base, &[("database.name", Some(Value::from(" ")))]);
+    assert!(invalid.is_err());
+
+    fs::write(
+        &path,
+        "# Work setup\neditor = \"vi\" # not nano\n\n[output]\n# as a table\nformat = \"csv\"\n\n[aliases]\nls = \"\"\"\nlist --all\"\"\"\n",
+    )
+    .unwrap();
+    write_changes(
+        &path,
+        &base,
+        &[
+            ("editor", Some(Value::from("hx"))),
+            ("aliases.ls", None),
+            ("aliases.next", Some(Value::from("next --limit 3"))),
+            ("confirm", Some(Value::from("bulk"))),
+            ("database.name", Some(Value::from("work"))),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "# Work setup\neditor = \"hx\" # not nano\nconfirm = \"bulk\"\n\n[output]\n# as a table\nformat = \"csv\"\n\n[aliases]\nnext = \"next --limit 3\"\n\n[database]\nname = \"work\"\n"
+    );
+
+    fs::write(&path, "output = { format = \"csv\" }\n").unwrap();
+    let inline = write_changes(
+        &path,
+        &base,
+        &[("output.color", Some(Value::from("never")))],
+    );
+    assert!(inline.err().unwrap().to_string().contains("in place"));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(with_suffix(&path, ".lock"));
+}
+
+#[test]
+fn entries_test() {
+    assert_eq!(parse_value("urgency.due", "3").unwrap(), Value::Float(3.0));
+    assert!(parse_value("urgency.due", "soon").is_err());
+    assert_eq!(
+        parse_value("aliases.ls", "list --all").unwrap(),
+        Value::from("list --all")
+    );
+    assert!(parse_value("aliases", "list").is_err());
+    assert!(parse_value("database.nme", "work").is_err());
+
+    let root = env::temp_dir().join(format!("xcon-entries-{}", std::process::id()));
+    let paths = Paths {
+        config: root.join(crate::config::CONFIG_FILE),
+        legacy_config: None,
+        data_dir: root.clone(),
+        custom_config: false,
+    };
+    fs::create_dir_all(&root).unwrap();
+    fs::write(&paths.config, "[output]\nformat = \"csv\"\n").unwrap();
+    let config = ConfigFile::load(&paths).unwrap();
+    let origin = |key: &str| {
+        config
+            .entries(&paths)
+            .into_iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| (entry.raw_value(), entry.origin))
+    };
+    assert_eq!(
+        origin("output.format"),
+        Some(("csv".to_owned(), Origin::File))
+    );
+    assert_eq!(
+        origin("database.name"),
+        Some(("todo".to_owned(), Origin::Default))
+    );
+    assert_eq!(origin("editor"), None);
+    assert_eq!(Origin::File.max(Origin::Flag), Origin::Flag);
+    assert_eq!(Origin::Flag.to_string(), "flag");
+    let _ = fs::remove_dir_all(&root);
+}
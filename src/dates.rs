@@ -0,0 +1,211 @@
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use std::fmt::Write;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+/// Parses a due date given as ISO date / date time or as a phrase like
+/// `today`, `tomorrow`, `eod`, `eow`, `eom`, `fri`, `next fri` or `in 3 days`.
+/// Dates without a time resolve to the end of that day
+pub fn parse_due(input: &str, now: DateTime<Local>) -> Result<DateTime<Utc>, String> {
+    parse_date(input, now, NaiveTime::from_hms_opt(23, 59, 59))
+}
+
+/// Parses a date like [`parse_due`] and also `yesterday` or `3 days ago`,
+/// dates without a time resolve to the start of that day
+pub fn parse_since(input: &str, now: DateTime<Local>) -> Result<DateTime<Utc>, String> {
+    parse_date(input, now, NaiveTime::from_hms_opt(0, 0, 0))
+}
+
+fn parse_date(
+    input: &str,
+    now: DateTime<Local>,
+    time: Option<NaiveTime>,
+) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    // Only the phrases are matched without case, ISO dates keep their `T`
+    let phrase = input.to_lowercase();
+    let today = now.date_naive();
+    let time = time.unwrap_or_else(|| now.time());
+    let at = |date: NaiveDate| local_to_utc(date.and_time(time));
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    let date = match words.as_slice() {
+        ["today"] | ["eod"] => at(today),
+        ["tomorrow"] => at(today + Duration::days(1)),
+        ["yesterday"] => at(today - Duration::days(1)),
+        ["eow"] => at(today + Duration::days(6 - days_from_monday(today))),
+        ["eom"] => at(last_day_of_month(today)),
+        ["next", day] => weekday(day).and_then(|day| {
+            let next_monday = today + Duration::days(7 - days_from_monday(today));
+            at(next_monday + Duration::days(i64::from(day.num_days_from_monday())))
+        }),
+        ["in", count, unit] => count
+            .parse::<i64>()
+            .ok()
+            .and_then(|count| add_period(now, count, unit, time)),
+        [count, unit, "ago"] => count
+            .parse::<i64>()
+            .ok()
+            .and_then(|count| add_period(now, -count, unit, time)),
+        [day] => weekday(day)
+            .and_then(|day| {
+                let ahead =
+                    (i64::from(day.num_days_from_monday()) - days_from_monday(today)).rem_euclid(7);
+                let ahead = if ahead == 0 { 7 } else { ahead };
+                at(today + Duration::days(ahead))
+            })
+            .or_else(|| parse_iso(input, time)),
+        _ => parse_iso(input, time),
+    };
+    date.ok_or_else(|| format!("Not a valid date '{}'", input))
+}
+
+/// Fails on a strftime format chrono can not render, like `%Q`
+pub fn check_date_format(format: &str) -> Result<(), String> {
+    let mut probe = String::new();
+    write!(probe, "{}", Utc::now().format(format))
+        .map_err(|_| format!("Not a valid date format '{}'", format))
+}
+
+/// Describes the due date relative to now, `due in 2d`, `due in 5h` or `3d overdue`
+pub fn relative(due: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let remaining = due - now;
+    if remaining.num_minutes() == 0 {
+        "due now".to_owned()
+    } else if remaining > Duration::zero() {
+        format!("due in {}", humanize(remaining))
+    } else {
+        format!("{} overdue", humanize(-remaining))
+    }
+}
+
+fn humanize(duration: Duration) -> String {
+    if duration.num_days() > 0 {
+        format!("{}d", duration.num_days())
+    } else if duration.num_hours() > 0 {
+        format!("{}h", duration.num_hours())
+    } else {
+        format!("{}m", duration.num_minutes())
+    }
+}
+
+fn add_period(
+    now: DateTime<Local>,
+    count: i64,
+    unit: &str,
+    time: NaiveTime,
+) -> Option<DateTime<Utc>> {
+    let today = now.date_naive();
+    match unit.trim_end_matches('s') {
+        "min" | "minute" => Some((now + Duration::minutes(count)).with_timezone(&Utc)),
+        "hour" => Some((now + Duration::hours(count)).with_timezone(&Utc)),
+        "day" => local_to_utc((today + Duration::days(count)).and_time(time)),
+        "week" => local_to_utc((today + Duration::weeks(count)).and_time(time)),
+        _ => None,
+    }
+}
+
+fn parse_iso(input: &str, time: NaiveTime) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(input) {
+        return Some(date.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return local_to_utc(date.and_time(time));
+    }
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .next()
+        .and_then(local_to_utc)
+}
+
+fn weekday(name: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(full_name, _)| name.len() >= 3 && full_name.starts_with(name))
+        .map(|(_, day)| *day)
+}
+
+fn days_from_monday(date: NaiveDate) -> i64 {
+    i64::from(date.weekday().num_days_from_monday())
+}
+
+fn last_day_of_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(date) - Duration::days(1)
+}
+
+fn local_to_utc(date: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+fn local(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Local> {
+    let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    Local
+        .from_local_datetime(&date.and_hms_opt(hour, 0, 0).unwrap())
+        .unwrap()
+}
+
+#[test]
+fn parse_due_phrases_test() {
+    // 2026-10-14 is a Wednesday
+    let now = local(2026, 10, 14, 9);
+    let end_of = |day| {
+        let date = NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+        local_to_utc(date.and_hms_opt(23, 59, 59).unwrap())
+    };
+    assert_eq!(parse_due("eod", now).ok(), end_of(14));
+    assert_eq!(parse_due("tomorrow", now).ok(), end_of(15));
+    assert_eq!(parse_due("fri", now).ok(), end_of(16));
+    assert_eq!(parse_due("wed", now).ok(), end_of(21));
+    assert_eq!(parse_due("next fri", now).ok(), end_of(23));
+    assert_eq!(parse_due("in 3 days", now).ok(), end_of(17));
+    assert_eq!(parse_due("2026-10-20", now).ok(), end_of(20));
+    let ten = NaiveDate::from_ymd_opt(2026, 10, 20)
+        .unwrap()
+        .and_hms_opt(10, 0, 0)
+        .unwrap();
+    assert_eq!(parse_due("2026-10-20T10:00", now).ok(), local_to_utc(ten));
+    assert_eq!(parse_due(" 2026-10-20 10:00 ", now).ok(), local_to_utc(ten));
+    assert_eq!(parse_due("Tomorrow", now).ok(), end_of(15));
+    assert_eq!(parse_due("eom", now).ok(), end_of(31));
+    assert!(parse_due("someday", now).is_err());
+}
+
+#[test]
+fn parse_since_test() {
+    let now = local(2026, 10, 14, 9);
+    let start_of = |day| {
+        let date = NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+        local_to_utc(date.and_hms_opt(0, 0, 0).unwrap())
+    };
+    assert_eq!(parse_since("yesterday", now).ok(), start_of(13));
+    assert_eq!(parse_since("3 days ago", now).ok(), start_of(11));
+    assert_eq!(parse_since("2026-10-01", now).ok(), start_of(1));
+}
+
+#[test]
+fn relative_test() {
+    let now = local(2026, 10, 14, 9).with_timezone(&Utc);
+    assert_eq!(relative(now + Duration::hours(50), now), "due in 2d");
+    assert_eq!(relative(now - Duration::days(3), now), "3d overdue");
+    assert_eq!(relative(now + Duration::hours(5), now), "due in 5h");
+}
@@ -1,23 +1,44 @@
-use crate::config::CONFIG_FILE;
-use crate::persistence::{db_action, init_db, CrudAction, Response};
+use crate::config_file::{
+    parse_value, write_changes, Color, ConfigChange, ConfigEntry, ConfigFile, Confirm, Origin,
+};
+use crate::output::Format;
+use crate::paths::Paths;
+use crate::store::{Backend, Change};
+use chrono::{DateTime, Utc};
 use log::{info, warn};
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use std::error;
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use toml::Value;
 
-const DEFAULT_DB_NAME: &str = "todo";
 const ROOT_USER: &str = "root";
+const SECONDS_PER_DAY: f64 = 86_400.0;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Todo {
     pub id: Option<i32>,
     pub title: String,
+    #[serde(default)]
     pub content: String,
     pub user_name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
 }
 impl Todo {
     pub fn new(title: String, content: String) -> Self {
@@ -26,175 +47,948 @@ impl Todo {
             title,
             content,
             user_name: Some(ROOT_USER.to_owned()),
+            tags: Vec::new(),
+            status: Status::Open,
+            completed_at: None,
+            due: None,
+            priority: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+    /// Taskwarrior style urgency, the weighted sum of priority, due date proximity,
+    /// age and tag factors each ranging from 0 to 1
+    pub fn urgency(&self, weights: &Urgency, now: DateTime<Utc>) -> f64 {
+        let priority = match self.priority {
+            Some(Priority::High) => 1.0,
+            Some(Priority::Medium) => 0.65,
+            Some(Priority::Low) => 0.3,
+            None => 0.0,
+        };
+        let due = self.due.map_or(0.0, |due| {
+            let overdue_days = (now - due).num_seconds() as f64 / SECONDS_PER_DAY;
+            if overdue_days >= 7.0 {
+                1.0
+            } else if overdue_days >= -14.0 {
+                (overdue_days + 14.0) * 0.8 / 21.0 + 0.2
+            } else {
+                0.2
+            }
+        });
+        let age = self.created_at.map_or(0.0, |created_at| {
+            let age_days = (now - created_at).num_seconds() as f64 / SECONDS_PER_DAY;
+            (age_days / 365.0).clamp(0.0, 1.0)
+        });
+        let tags = match self.tags.len() {
+            0 => 0.0,
+            1 => 0.8,
+            2 => 0.9,
+            _ => 1.0,
+        };
+        weights.priority * priority + weights.due * due + weights.age * age + weights.tags * tags
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    #[serde(rename = "L")]
+    Low = 1,
+    #[serde(rename = "M")]
+    Medium = 2,
+    #[serde(rename = "H")]
+    High = 3,
+}
+impl Priority {
+    pub fn level(self) -> i64 {
+        self as i64
+    }
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "L",
+            Priority::Medium => "M",
+            Priority::High => "H",
+        }
+    }
+    pub fn from_level(level: i64) -> Option<Self> {
+        match level {
+            1 => Some(Priority::Low),
+            2 => Some(Priority::Medium),
+            3 => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+impl FromStr for Priority {
+    type Err = String;
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority.trim().to_lowercase().as_str() {
+            "l" | "low" | "1" => Ok(Priority::Low),
+            "m" | "medium" | "2" => Ok(Priority::Medium),
+            "h" | "high" | "3" => Ok(Priority::High),
+            _ => Err(format!(
+                "Not a valid priority '{}', use H/M/L or 3/2/1",
+                priority
+            )),
         }
     }
 }
+
+/// Coefficients of the urgency factors, configured in the `[urgency]` table
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Urgency {
+    pub priority: f64,
+    pub due: f64,
+    pub age: f64,
+    pub tags: f64,
+}
+impl Default for Urgency {
+    fn default() -> Self {
+        Self {
+            priority: 6.0,
+            due: 12.0,
+            age: 2.0,
+            tags: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Open,
+    Done,
+}
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Open => "open",
+            Status::Done => "done",
+        }
+    }
+}
+impl FromStr for Status {
+    type Err = String;
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "open" => Ok(Status::Open),
+            "done" => Ok(Status::Done),
+            _ => Err(format!("Unknown status {}", status)),
+        }
+    }
+}
+
+/// Criteria applied when listing todo, an empty filter matches everything
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    pub tags: Vec<String>,
+    /// Match todo having any of the tags instead of all of them
+    pub any_tag: bool,
+    /// Only todo in this status, every status when empty
+    pub status: Option<Status>,
+    /// Only todo due before this time
+    pub due_before: Option<DateTime<Utc>>,
+    /// Only todo created at or after this time
+    pub created_since: Option<DateTime<Utc>>,
+    /// Only todo created at or before this time
+    pub created_until: Option<DateTime<Utc>>,
+    pub sort: Sort,
+    /// Flip the natural order of the sort field
+    pub reverse: bool,
+    /// At most this many todo, every matching todo when empty
+    pub limit: Option<i64>,
+    /// Number of matching todo skipped before the first one returned
+    pub offset: i64,
+}
+
+/// Ordering of listed todo. Urgency, priority and timestamps list the highest
+/// or most recent first, due dates the soonest first, title and id ascending
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    #[default]
+    Urgency,
+    Created,
+    Updated,
+    Due,
+    Priority,
+    Title,
+    Id,
+}
+impl FromStr for Sort {
+    type Err = String;
+    fn from_str(sort: &str) -> Result<Self, Self::Err> {
+        match sort.trim().to_lowercase().as_str() {
+            "urgency" => Ok(Sort::Urgency),
+            "created" => Ok(Sort::Created),
+            "updated" => Ok(Sort::Updated),
+            "due" => Ok(Sort::Due),
+            "priority" => Ok(Sort::Priority),
+            "title" => Ok(Sort::Title),
+            "id" => Ok(Sort::Id),
+            _ => Err(format!(
+                "Not a valid sort '{}', use urgency, created, updated, due, priority, title or id",
+                sort
+            )),
+        }
+    }
+}
+
+/// FTS5 query, supporting phrases, prefixes and boolean operators
+#[derive(Debug)]
+pub struct Search {
+    pub query: String,
+    pub limit: i64,
+    /// Wrapped around every match in the highlighted title and snippet
+    pub markers: (String, String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SearchHit {
+    pub todo: Todo,
+    pub title: String,
+    pub snippet: String,
+    /// bm25 score, computed by FTS5 in SQLite and the same way in memory otherwise,
+    /// lower is a better match
+    pub rank: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub count: i64,
+}
+
+/// Schema version of a store and the migrations, by version, it is missing
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SchemaVersion {
+    pub current: i64,
+    pub latest: i64,
+    pub pending: Vec<(i64, String)>,
+}
+
 pub type ID = i64;
 pub enum Action {
     Save(Todo),
-    Fetch, //TODO : Pagination
+    Fetch(Filter),
+    /// Hands every matching todo to the sink as it is read instead of collecting them,
+    /// the sink returns false to stop early
+    Stream(Filter, Box<dyn FnMut(Todo) -> bool>),
     FetchById(ID),
+    Update(Todo),
+    Complete(ID),
+    Reopen(ID),
     Delete,
     DeleteById(ID),
+    FetchTags,
+    MergeTags(Vec<String>, String),
+    Search(Search),
+    /// Applies all of the changes or none of them
+    Batch(Vec<Change>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub enum TodoResponse {
     Done,
     One(Option<Todo>),
     All(Vec<Todo>),
-    Empty,
+    Tags(Vec<Tag>),
+    Schema(SchemaVersion),
+    Hits(Vec<SearchHit>),
+    /// Number of todo handed to the sink of a stream
+    Streamed(usize),
+    /// Number of changes applied by a batch
+    Applied(usize),
+    /// Config keys written
+    Configured(Vec<ConfigChange>),
+    /// Id given to a todo copied or moved into another database
+    Transferred(ID),
 }
 
 #[derive(Debug)]
 pub struct TodoError {
     msg: String,
     error_type: TodoErrorType,
+    context: Option<String>,
+    source: Option<Box<dyn error::Error + 'static>>,
 }
-#[derive(Debug)]
+/// Category of a failure, each one exits the process with its own code
+///
+/// | code | error                |                                              |
+/// |------|----------------------|----------------------------------------------|
+/// | 0    |                      | success                                      |
+/// | 1    | `unable_to_initialize`, `storage_failure` | unexpected failure      |
+/// | 2    | `invalid_input`, `invalid_query` | bad arguments, same as usage errors |
+/// | 3    | `not_initialized`    | `xcon init` has not been run                 |
+/// | 4    | `not_found`          | no such record or tag                        |
+/// | 5    | `conflict`           | the change clashes with existing data        |
+/// | 6    | `locked`             | the store is busy in another process         |
+/// | 7    | `permission_denied`  | the store or config can not be written       |
+/// | 8    | `storage_corrupt`    | the store is damaged or not a database       |
+/// | 9    | `incompatible_schema`| the store was migrated by a newer xcon       |
+/// | 10   | `test_failed`        | `xcon test` found the store unhealthy        |
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum TodoErrorType {
+    #[serde(rename = "not_initialized")]
     InitNotAvailable,
     UnableToInitialize,
     TestFailed,
+    #[serde(rename = "not_found")]
     RecordNotFound,
+    IncompatibleSchema,
+    InvalidQuery,
+    InvalidInput,
+    StorageFailure,
+    StorageCorrupt,
+    Locked,
+    PermissionDenied,
+    Conflict,
+}
+impl TodoErrorType {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            TodoErrorType::UnableToInitialize | TodoErrorType::StorageFailure => 1,
+            TodoErrorType::InvalidInput | TodoErrorType::InvalidQuery => 2,
+            TodoErrorType::InitNotAvailable => 3,
+            TodoErrorType::RecordNotFound => 4,
+            TodoErrorType::Conflict => 5,
+            TodoErrorType::Locked => 6,
+            TodoErrorType::PermissionDenied => 7,
+            TodoErrorType::StorageCorrupt => 8,
+            TodoErrorType::IncompatibleSchema => 9,
+            TodoErrorType::TestFailed => 10,
+        }
+    }
+    fn message(self) -> &'static str {
+        match self {
+            TodoErrorType::InitNotAvailable => "Please initialize application,use help",
+            TodoErrorType::UnableToInitialize => {
+                "Unable to initizlize application, contact support"
+            }
+            TodoErrorType::TestFailed => "Database check has failed",
+            TodoErrorType::RecordNotFound => "Record not found",
+            TodoErrorType::IncompatibleSchema => {
+                "Db store was created by a newer version, please upgrade"
+            }
+            TodoErrorType::InvalidQuery => "Not a valid search query",
+            TodoErrorType::InvalidInput => "Not a valid input",
+            TodoErrorType::StorageFailure => "Unable to access the todo store",
+            TodoErrorType::StorageCorrupt => "Db store is damaged or not a todo store",
+            TodoErrorType::Locked => "Db store is locked by another process, try again",
+            TodoErrorType::PermissionDenied => "Permission denied",
+            TodoErrorType::Conflict => "The change conflicts with existing data",
+        }
+    }
 }
 impl TodoError {
     pub fn build(todo: TodoErrorType) -> TodoError {
-        match todo {
-            TodoErrorType::InitNotAvailable => TodoError {
-                msg: "Please initialize application,use help".to_owned(),
-                error_type: TodoErrorType::InitNotAvailable,
-            },
-            TodoErrorType::UnableToInitialize => TodoError {
-                msg: "Unable to initizlize application, contact support".to_owned(),
-                error_type: TodoErrorType::UnableToInitialize,
-            },
-            TodoErrorType::TestFailed => TodoError {
-                msg: "Database check has failed".to_owned(),
-                error_type: TodoErrorType::UnableToInitialize,
-            },
-            TodoErrorType::RecordNotFound => TodoError {
-                msg: "Record Not Found".to_owned(),
-                error_type: TodoErrorType::RecordNotFound,
-            },
+        TodoError::with_message(todo, todo.message())
+    }
+    pub fn with_message(todo: TodoErrorType, msg: impl Into<String>) -> TodoError {
+        TodoError {
+            msg: msg.into(),
+            error_type: todo,
+            context: None,
+            source: None,
         }
     }
+    /// Invalid argument or input, with the message telling what is wrong
+    pub fn invalid_input(msg: impl Into<String>) -> TodoError {
+        TodoError::with_message(TodoErrorType::InvalidInput, msg)
+    }
+    /// Builds the error keeping the underlying cause available through `Error::source`
+    pub fn caused_by(todo: TodoErrorType, source: impl error::Error + 'static) -> TodoError {
+        TodoError {
+            source: Some(Box::new(source)),
+            ..Self::build(todo)
+        }
+    }
+    /// Failure of a file operation, denied permissions are reported as such
+    pub fn io(todo: TodoErrorType, source: std::io::Error) -> TodoError {
+        match source.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                TodoError::caused_by(TodoErrorType::PermissionDenied, source)
+            }
+            _ => TodoError::caused_by(todo, source),
+        }
+    }
+    /// Describes what was being done when the error happened, like `Save has failed`
+    pub fn context(self, context: impl Into<String>) -> TodoError {
+        TodoError {
+            context: Some(context.into()),
+            ..self
+        }
+    }
+    pub fn is(&self, error_type: TodoErrorType) -> bool {
+        self.error_type == error_type
+    }
+    pub fn error_type(&self) -> TodoErrorType {
+        self.error_type
+    }
+    pub fn get_context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+    pub fn exit_code(&self) -> i32 {
+        self.error_type.exit_code()
+    }
 }
 impl fmt::Display for TodoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid first item to double")
+        write!(f, "{}", self.msg)
     }
 }
 
 // This is important for other errors to wrap this one.
 impl error::Error for TodoError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
-        None
+        self.source.as_deref()
     }
 }
 
+/// Database in the data directory with the number of todo and bytes it holds
+#[derive(Debug, PartialEq)]
+pub struct DbSummary {
+    pub name: String,
+    /// Not known when the database could not be opened
+    pub todo: Option<usize>,
+    pub size: u64,
+    /// Used when no `--db` is given
+    pub default: bool,
+}
+
+/// Name of a database given to the `db` commands, a single word of letters, digits,
+/// `-` and `_` so it is a plain file name for every backend
+pub fn check_db_name(name: &str) -> Result<String, TodoError> {
+    let name = name.trim().to_lowercase();
+    let valid = name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || !valid {
+        return Err(TodoError::invalid_input(format!(
+            "Not a valid database name '{}', use letters, digits, - and _",
+            name
+        )));
+    }
+    Ok(name)
+}
+
 pub enum Setup {
     Init,
     Test,
+    Migrate,
+    MigrationStatus,
+    Configure(ConfigurationArgument),
 }
-struct ConfigurationArgument {
-    db: bool,
-    set: bool,
+
+/// Global flags and the config keys `--set` keeps them in, `--unset` takes the flag names
+const GLOBAL_FLAGS: [(&str, &str); 2] = [("db", "database.name"), ("format", "output.format")];
+
+/// Global flags `--set` keeps as defaults in the config and the flags `--unset`
+/// reverts to their default
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigurationArgument {
+    /// Config file given with `--config`, never kept
+    pub config: Option<String>,
+    pub db: Option<String>,
+    pub format: Option<Format>,
+    pub set: bool,
+    pub unset: Vec<String>,
+}
+impl ConfigurationArgument {
+    /// Config keys with the values to write, `None` for the keys to remove
+    pub fn changes(&self) -> Vec<(&'static str, Option<Value>)> {
+        let mut changes = Vec::new();
+        if self.set {
+            if let Some(db) = &self.db {
+                changes.push((GLOBAL_FLAGS[0].1, Some(Value::from(db.as_str()))));
+            }
+            if let Some(format) = self.format {
+                changes.push((GLOBAL_FLAGS[1].1, Value::try_from(format).ok()));
+            }
+        }
+        for flag in &self.unset {
+            if let Some((_, key)) = GLOBAL_FLAGS.iter().find(|(name, _)| name == flag) {
+                changes.push((*key, None));
+            }
+        }
+        changes
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub struct Settings {
+    /// Database in use, the configured one unless `--db` names another
     pub db: String,
     pub is_saved: bool,
+    /// Locations of the config file and of the databases
+    pub paths: Paths,
+    /// Everything read from the config file
+    pub config: ConfigFile,
 }
 
+// The handlers are tested against MockSettings, which leaves most of these unused
+#[cfg_attr(test, allow(dead_code))]
 #[automock]
 impl Settings {
-    pub fn system_default() -> Self {
+    pub fn system_default(paths: Paths) -> Self {
+        Self::create(ConfigFile::default().database.name, false, paths)
+    }
+    pub fn create(db: String, is_saved: bool, paths: Paths) -> Self {
         Self {
-            db: DEFAULT_DB_NAME.to_string(),
-            is_saved: false,
+            db,
+            is_saved,
+            paths,
+            config: ConfigFile::default(),
         }
     }
-    pub fn create(db: String, is_saved: bool) -> Self {
-        Self { db, is_saved }
-    }
 
     pub fn update(&self, db: String) -> Self {
         Self {
             db,
             is_saved: self.is_saved,
+            paths: self.paths.clone(),
+            config: self.config.clone(),
         }
     }
+    /// Name of the database in use
+    pub fn get_db_name(&self) -> String {
+        self.db.clone()
+    }
+    /// Location of the database in the data directory
     pub fn get_db(&self) -> String {
-        format!("{}.store", self.db.to_owned())
+        let path = self.paths.data_file(&self.get_backend().path(&self.db));
+        path.to_string_lossy().into_owned()
+    }
+    pub fn get_config_file(&self) -> PathBuf {
+        self.paths.config.clone()
+    }
+    pub fn get_backend(&self) -> Backend {
+        self.config.database.backend
+    }
+    pub fn get_urgency(&self) -> Urgency {
+        self.config.urgency
+    }
+    pub fn get_template(&self, name: &str) -> Option<String> {
+        self.config.templates.get(name).cloned()
+    }
+    pub fn get_format(&self) -> Option<Format> {
+        self.config.output.format
+    }
+    pub fn get_date_format(&self) -> Option<String> {
+        self.config.output.date_format.clone()
+    }
+    pub fn get_color(&self) -> Color {
+        self.config.output.color
+    }
+    pub fn get_editor(&self) -> Option<String> {
+        self.config.editor.clone()
+    }
+    pub fn get_confirm(&self) -> Confirm {
+        self.config.confirm
+    }
+    pub fn get_alias(&self, name: &str) -> Option<Vec<String>> {
+        self.config.alias(name)
     }
     pub fn test_setup(&self, db: String) -> Result<TodoResponse, TodoError> {
-        match db_action(CrudAction::HealthCheck, db) {
-            Response::Success => Ok(TodoResponse::Done),
-            _ => Err(TodoError::build(TodoErrorType::InitNotAvailable)),
-        }
+        self.get_backend()
+            .open(&db)
+            .and_then(|mut store| store.check())
+            .map(|_| TodoResponse::Done)
+            .map_err(|why| TodoError::caused_by(TodoErrorType::TestFailed, why))
     }
+    /// Writes the database and backend into the config file, keeping its other settings.
+    /// A legacy config is converted on the first write
     pub fn write_default_config(&self) -> Result<TodoResponse, TodoError> {
-        let file_options = OpenOptions::new().write(true).open(CONFIG_FILE);
-
-        match file_options {
-            Ok(mut file) => match file.write(self.to_string().as_bytes()) {
-                Ok(_) => Ok(TodoResponse::Done),
-                Err(why) => {
-                    info!("couldn't write to {}", why);
-                    Err(TodoError::build(TodoErrorType::UnableToInitialize))
-                }
-            },
-            Err(why) => {
+        let db = self.db.trim().replace(".store", "");
+        let changes = [
+            ("database.name", Some(Value::from(db))),
+            (
+                "database.backend",
+                Some(Value::from(self.get_backend().as_str())),
+            ),
+        ];
+        write_changes(&self.paths.config, &self.config, &changes)
+            .map(|_| TodoResponse::Done)
+            .map_err(|why| {
                 info!("couldn't write to {}", why);
-                Err(TodoError::build(TodoErrorType::UnableToInitialize))
+                why
+            })
+    }
+    /// Writes the flags given with `--set` into the config file and removes those
+    /// named by `--unset`, a legacy config is converted on the first write
+    pub fn write_custom_config(
+        &self,
+        argument: &ConfigurationArgument,
+    ) -> Result<TodoResponse, TodoError> {
+        let changes = argument.changes();
+        if changes.is_empty() {
+            return Err(TodoError::invalid_input(
+                "Nothing to set, pass --db or --format along with --set",
+            ));
+        }
+        write_changes(&self.paths.config, &self.config, &changes).map(TodoResponse::Configured)
+    }
+    /// Sets a single key of the config file from its value on the command line
+    pub fn set_config(&self, key: &str, value: &str) -> Result<TodoResponse, TodoError> {
+        let value = parse_value(key, value)?;
+        write_changes(&self.paths.config, &self.config, &[(key, Some(value))])
+            .map(TodoResponse::Configured)
+    }
+    /// Location of the config file that is read, written with the defaults when
+    /// there is none yet
+    pub fn write_config_file(&self) -> Result<PathBuf, TodoError> {
+        let path = self.paths.config_source().to_path_buf();
+        if !path.exists() {
+            write_changes(&path, &self.config, &[])?;
+        }
+        Ok(path)
+    }
+    /// Reads the config file again and reports the first invalid key
+    pub fn validate_config(&self) -> Result<TodoResponse, TodoError> {
+        ConfigFile::load(&self.paths).map(|_| TodoResponse::Done)
+    }
+    /// Effective value of every config key and where it comes from, along with the
+    /// locations of the config and of the database in use. Those locations are
+    /// derived from the other keys and the environment and cannot be set
+    pub fn config_entries(&self, flags: &ConfigurationArgument) -> Vec<ConfigEntry> {
+        let entry = |key: &str, value: Value, origin| ConfigEntry {
+            key: key.to_owned(),
+            value,
+            origin,
+        };
+        let path = |path: &PathBuf| Value::from(path.to_string_lossy().into_owned());
+        let mut entries = self.config.entries(&self.paths);
+        let flagged = [
+            ("database.name", flags.db.as_deref().map(Value::from)),
+            (
+                "output.format",
+                flags.format.and_then(|format| Value::try_from(format).ok()),
+            ),
+        ];
+        for (key, value) in flagged.iter() {
+            if let Some(value) = value {
+                entries.retain(|entry| entry.key != *key);
+                entries.push(entry(key, value.clone(), Origin::Flag));
             }
         }
+        let derived = [
+            ("paths.config", path(&self.paths.config)),
+            ("paths.data_dir", path(&self.paths.data_dir)),
+            ("database.path", Value::from(self.get_db())),
+        ];
+        for (key, value) in derived.iter() {
+            entries.push(entry(key, value.clone(), Origin::Derived));
+        }
+        entries.sort_by(|entry, other| entry.key.cmp(&other.key));
+        entries
     }
-    pub fn write_custom_config(&self) -> Result<TodoResponse, TodoError> {
-        Ok(TodoResponse::Done)
+    /// Names of the databases of the configured backend in the data directory
+    pub fn db_names(&self) -> Result<Vec<String>, TodoError> {
+        self.get_backend()
+            .databases(&self.paths.data_dir)
+            .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))
+    }
+    /// Databases of the configured backend in the data directory
+    pub fn list_dbs(&self) -> Result<Vec<DbSummary>, TodoError> {
+        let backend = self.get_backend();
+        let names = self.db_names()?;
+        let summaries = names
+            .into_iter()
+            .map(|name| {
+                let path = self.update(name.clone()).get_db();
+                let todo = backend
+                    .count(&path)
+                    .map_err(|why| warn!("Unable to read the database {} : {}", name, why))
+                    .ok();
+                DbSummary {
+                    default: name == self.config.database.name,
+                    size: backend.size(&path),
+                    name,
+                    todo,
+                }
+            })
+            .collect();
+        Ok(summaries)
+    }
+    pub fn create_db(&self, name: &str) -> Result<TodoResponse, TodoError> {
+        let db = self.update(check_db_name(name)?);
+        if self.get_backend().exists(&db.get_db()) {
+            return Err(TodoError::with_message(
+                TodoErrorType::Conflict,
+                format!("Database {} already exists", db.db),
+            ));
+        }
+        db.initalize_db()
+    }
+    /// Renames the database, the default follows it when it is the one renamed
+    pub fn rename_db(&self, from: &str, to: &str) -> Result<TodoResponse, TodoError> {
+        let source = self.update(self.existing_db(from)?);
+        let target = self.update(check_db_name(to)?);
+        if self.get_backend().exists(&target.get_db()) {
+            return Err(TodoError::with_message(
+                TodoErrorType::Conflict,
+                format!("Database {} already exists", target.db),
+            ));
+        }
+        self.get_backend()
+            .rename(&source.get_db(), &target.get_db())?;
+        if source.db != self.config.database.name {
+            return Ok(TodoResponse::Done);
+        }
+        let changes = [("database.name", Some(Value::from(target.db)))];
+        write_changes(&self.paths.config, &self.config, &changes).map(TodoResponse::Configured)
+    }
+    /// Deletes the database, the default one is kept until another is used
+    pub fn drop_db(&self, name: &str) -> Result<TodoResponse, TodoError> {
+        let db = self.update(self.existing_db(name)?);
+        if db.db == self.config.database.name {
+            return Err(TodoError::with_message(
+                TodoErrorType::Conflict,
+                format!(
+                    "Database {} is the default, use another one with `db use` first",
+                    db.db
+                ),
+            ));
+        }
+        self.get_backend()
+            .remove(&db.get_db())
+            .map(|_| TodoResponse::Done)
+            .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))
+    }
+    /// Makes the database the default one
+    pub fn use_db(&self, name: &str) -> Result<TodoResponse, TodoError> {
+        let db = self.existing_db(name)?;
+        let changes = [("database.name", Some(Value::from(db)))];
+        write_changes(&self.paths.config, &self.config, &changes).map(TodoResponse::Configured)
+    }
+    /// Checked name of a database that exists
+    fn existing_db(&self, name: &str) -> Result<String, TodoError> {
+        let name = check_db_name(name)?;
+        if self
+            .get_backend()
+            .exists(&self.update(name.clone()).get_db())
+        {
+            Ok(name)
+        } else {
+            Err(TodoError::with_message(
+                TodoErrorType::RecordNotFound,
+                format!(
+                    "Database {} not found, create it with `db create {}`",
+                    name, name
+                ),
+            ))
+        }
     }
     //TODO : Improve with Option<Configuration> for load_config()
     pub fn is_config_available(&self) -> bool {
         self.is_saved
     }
     pub fn initalize_db(&self) -> Result<TodoResponse, TodoError> {
-        let db = format!("{}.store", self.db);
-        match init_db(&db) {
+        if !self.paths.data_dir.as_os_str().is_empty() {
+            fs::create_dir_all(&self.paths.data_dir)
+                .map_err(|why| TodoError::io(TodoErrorType::UnableToInitialize, why))?;
+        }
+        match self.get_backend().init(&self.get_db()) {
             Ok(_) => Ok(TodoResponse::Done),
             Err(why) => {
                 warn!("Unable to initiazlize the DB {}", why);
-                Err(TodoError::build(TodoErrorType::UnableToInitialize))
+                Err(TodoError::caused_by(TodoErrorType::UnableToInitialize, why))
             }
         }
     }
 
-    pub fn load_config() -> Result<Self, TodoError> {
-        match File::open(CONFIG_FILE) {
-            Ok(config_file) => {
-                let buf_reader = BufReader::new(config_file);
-                let mut db: String = DEFAULT_DB_NAME.to_owned();
+    pub fn migrate_db(&self) -> Result<TodoResponse, TodoError> {
+        schema_response(self.get_backend().migrate(&self.get_db()))
+    }
+    pub fn schema_status(&self) -> Result<TodoResponse, TodoError> {
+        schema_response(self.get_backend().schema_status(&self.get_db()))
+    }
 
-                for (_, line) in buf_reader.lines().enumerate() {
-                    let line = line.unwrap();
-                    let split = line.split("=");
-                    let vec = split.collect::<Vec<&str>>();
+    pub fn load_config(paths: Paths) -> Result<Self, TodoError> {
+        let config = ConfigFile::load(&paths)?;
+        Ok(Self {
+            db: config.database.name.clone(),
+            is_saved: true,
+            paths,
+            config,
+        })
+    }
+}
+fn schema_response(result: Result<SchemaVersion, TodoError>) -> Result<TodoResponse, TodoError> {
+    result.map(TodoResponse::Schema).map_err(|why| {
+        warn!("Unable to read the DB schema {}", why);
+        why
+    })
+}
+#[test]
+fn urgency_test() {
+    let now = Utc::now();
+    let weights = Urgency::default();
+    let plain = Todo::new("plain".to_owned(), String::new());
+    let urgent = Todo {
+        priority: Some(Priority::High),
+        due: Some(now - chrono::Duration::days(7)),
+        ..Todo::new("urgent".to_owned(), String::new())
+    };
+    assert_eq!(plain.urgency(&weights, now), 0.0);
+    assert_eq!(urgent.urgency(&weights, now), 18.0);
+}
 
-                    match vec[0] {
-                        "db" => db = vec[1].trim().to_string(),
-                        _ => (),
-                    }
-                }
-                Ok(Settings::create(db, true))
-            }
-            Err(_) => Err(TodoError::build(TodoErrorType::InitNotAvailable)),
+#[test]
+fn configuration_argument_test() {
+    let argument = ConfigurationArgument {
+        config: None,
+        db: Some("work".to_owned()),
+        format: Some(Format::Csv),
+        set: true,
+        unset: vec!["db".to_owned()],
+    };
+    assert_eq!(
+        argument.changes(),
+        vec![
+            ("database.name", Some(Value::from("work"))),
+            ("output.format", Some(Value::from("csv"))),
+            ("database.name", None),
+        ]
+    );
+    let without_set = ConfigurationArgument {
+        set: false,
+        ..argument
+    };
+    assert_eq!(without_set.changes(), vec![("database.name", None)]);
+}
+
+#[test]
+fn settings_test() {
+    let root = std::env::temp_dir().join(format!("xcon-settings-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let paths = Paths {
+        config: root.join(crate::config::CONFIG_FILE),
+        legacy_config: None,
+        data_dir: root.join("data"),
+        custom_config: true,
+    };
+    let settings = Settings::system_default(paths.clone());
+    let argument = ConfigurationArgument {
+        config: None,
+        db: Some("work".to_owned()),
+        format: None,
+        set: true,
+        unset: Vec::new(),
+    };
+    match settings.write_custom_config(&argument).unwrap() {
+        TodoResponse::Configured(changes) => {
+            assert_eq!(
+                changes[0].to_string(),
+                "database.name = \"work\" (was \"todo\")"
+            )
         }
+        response => panic!("unexpected response {:?}", response),
     }
+    let settings = Settings::load_config(paths).unwrap();
+    assert_eq!(settings.get_db_name(), "work");
+    let origin = |key: &str| {
+        settings
+            .config_entries(&argument)
+            .into_iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.origin)
+    };
+    assert_eq!(origin("database.name"), Some(Origin::Flag));
+    assert_eq!(origin("paths.data_dir"), Some(Origin::Derived));
+    assert_eq!(origin("database.path"), Some(Origin::Derived));
+    assert!(settings.set_config("database.path", "elsewhere").is_err());
+
+    let current = |response| match response {
+        Ok(TodoResponse::Schema(version)) => (version.current, version.pending.is_empty()),
+        response => panic!("unexpected response {:?}", response),
+    };
+    assert!(settings.schema_status().is_err());
+    assert_eq!(settings.initalize_db().unwrap(), TodoResponse::Done);
+    let (latest, _) = current(settings.schema_status());
+    assert_eq!(current(settings.migrate_db()), (latest, true));
+    let _ = fs::remove_dir_all(&root);
 }
-impl fmt::Display for Settings {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "db={} \n", &self.db.trim().replace(".store", ""))
+
+#[test]
+fn db_commands_test() {
+    let root = std::env::temp_dir().join(format!("xcon-dbs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let paths = Paths {
+        config: root.join(crate::config::CONFIG_FILE),
+        legacy_config: None,
+        data_dir: root.join("data"),
+        custom_config: true,
+    };
+    let settings = Settings::system_default(paths.clone());
+    settings.initalize_db().unwrap();
+    assert_eq!(settings.create_db("Work").unwrap(), TodoResponse::Done);
+    assert!(settings
+        .create_db("work")
+        .err()
+        .unwrap()
+        .is(TodoErrorType::Conflict));
+    assert!(settings.create_db("../work").is_err());
+
+    assert!(matches!(
+        settings.rename_db("todo", "main").unwrap(),
+        TodoResponse::Configured(_)
+    ));
+    let settings = Settings::load_config(paths.clone()).unwrap();
+    assert_eq!(settings.get_db_name(), "main");
+    assert!(settings
+        .rename_db("todo", "home")
+        .err()
+        .unwrap()
+        .is(TodoErrorType::RecordNotFound));
+    assert!(settings
+        .drop_db("main")
+        .err()
+        .unwrap()
+        .is(TodoErrorType::Conflict));
+
+    assert!(matches!(
+        settings.use_db("work").unwrap(),
+        TodoResponse::Configured(_)
+    ));
+    let settings = Settings::load_config(paths).unwrap();
+    assert_eq!(settings.drop_db("main").unwrap(), TodoResponse::Done);
+    let databases = settings.list_dbs().unwrap();
+    assert_eq!(
+        databases,
+        vec![DbSummary {
+            name: "work".to_owned(),
+            todo: Some(0),
+            size: databases[0].size,
+            default: true,
+        }]
+    );
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn exit_code_test() {
+    let documented = [
+        (TodoErrorType::UnableToInitialize, 1, "unable_to_initialize"),
+        (TodoErrorType::StorageFailure, 1, "storage_failure"),
+        (TodoErrorType::InvalidInput, 2, "invalid_input"),
+        (TodoErrorType::InvalidQuery, 2, "invalid_query"),
+        (TodoErrorType::InitNotAvailable, 3, "not_initialized"),
+        (TodoErrorType::RecordNotFound, 4, "not_found"),
+        (TodoErrorType::Conflict, 5, "conflict"),
+        (TodoErrorType::Locked, 6, "locked"),
+        (TodoErrorType::PermissionDenied, 7, "permission_denied"),
+        (TodoErrorType::StorageCorrupt, 8, "storage_corrupt"),
+        (TodoErrorType::IncompatibleSchema, 9, "incompatible_schema"),
+        (TodoErrorType::TestFailed, 10, "test_failed"),
+    ];
+    for (error_type, code, name) in documented.iter() {
+        let why = TodoError::build(*error_type).context("Context is kept");
+        assert_eq!(why.exit_code(), *code);
+        assert_eq!(serde_json::to_value(error_type).unwrap(), *name);
     }
+    let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+    let why = TodoError::io(TodoErrorType::StorageFailure, denied);
+    assert_eq!(why.exit_code(), 7);
 }
@@ -0,0 +1,124 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_EDITOR: &str = "vi";
+/// Names tried for the temporary file before giving up
+const TEMP_FILE_ATTEMPTS: u32 = 16;
+
+/// Opens the given title and content in the configured editor, `$VISUAL` or `$EDITOR`
+/// and returns the edited pair. The first line of the file is the title, everything
+/// after the blank line is the content
+pub fn edit_todo(title: &str, content: &str, editor: Option<String>) -> Result<(String, String)> {
+    let (path, mut file) = temp_file()?;
+    let written = file.write_all(compose(title, content).as_bytes());
+    drop(file);
+    if let Err(why) = written {
+        let _ = fs::remove_file(&path);
+        return Err(why);
+    }
+    let status = launch_editor(&path, editor);
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status?;
+    if !status.success() {
+        return Err(Error::other("Editor exited with failure"));
+    }
+    Ok(parse(&edited?))
+}
+
+/// Opens the file in the configured editor, `$VISUAL` or `$EDITOR`
+pub fn edit_file(path: &Path, editor: Option<String>) -> Result<()> {
+    if launch_editor(path, editor)?.success() {
+        Ok(())
+    } else {
+        Err(Error::other("Editor exited with failure"))
+    }
+}
+
+fn launch_editor(path: &Path, editor: Option<String>) -> Result<std::process::ExitStatus> {
+    let editor = editor
+        .ok_or(())
+        .or_else(|_| env::var("VISUAL"))
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_owned());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or(DEFAULT_EDITOR);
+    Command::new(program).args(parts).arg(path).status()
+}
+
+/// Creates a new file only the user can read in the temporary directory, under a
+/// name nobody could have prepared a file or a symlink at beforehand
+fn temp_file() -> Result<(PathBuf, File)> {
+    let mut last = Error::new(ErrorKind::AlreadyExists, "No free temporary file name");
+    for attempt in 0..TEMP_FILE_ATTEMPTS {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        let path = env::temp_dir().join(format!(
+            "xcon-edit-{}-{:08x}{:x}.txt",
+            std::process::id(),
+            nanos,
+            attempt
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(why) if why.kind() == ErrorKind::AlreadyExists => last = why,
+            Err(why) => return Err(why),
+        }
+    }
+    Err(last)
+}
+
+fn compose(title: &str, content: &str) -> String {
+    format!("{}\n\n{}\n", title, content)
+}
+
+/// Reads back what `compose` wrote, the content is kept as written after the blank
+/// line but for the newline ending the file
+fn parse(text: &str) -> (String, String) {
+    let (title, rest) = text.split_once('\n').unwrap_or((text, ""));
+    let content = match rest.split_once('\n') {
+        Some((separator, content)) if separator.trim().is_empty() => content,
+        None if rest.trim().is_empty() => "",
+        _ => rest,
+    };
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    (title.trim().to_owned(), content.to_owned())
+}
+
+#[test]
+fn compose_parse_round_trip_test() {
+    let text = compose("Groceries", "milk\neggs");
+    assert_eq!(
+        parse(&text),
+        ("Groceries".to_owned(), "milk\neggs".to_owned())
+    );
+    for content in &["", "\n\nindented\n  milk  \n\n", "\n"] {
+        assert_eq!(parse(&compose("Groceries", content)).1, *content);
+    }
+    assert_eq!(parse("Groceries\nmilk\n").1, "milk");
+    assert_eq!(parse("Groceries").1, "");
+}
+
+#[test]
+fn temp_file_test() {
+    let (path, _file) = temp_file().unwrap();
+    let (other, _) = temp_file().unwrap();
+    assert_ne!(path, other);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&other);
+}
@@ -1,14 +1,35 @@
 
 use cfg_if::cfg_if;
-use clap::ArgMatches;
-use log::info;
-use std::io::{stdin, stdout, Write};
+use chrono::{DateTime, Local, Utc};
+use clap::{App, ArgMatches, ArgSettings};
+use log::{info, warn};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, stdin, stdout, BufRead, IsTerminal, Read, Stdout, Write};
+use std::rc::Rc;
 
-use crate::service::action_router;
+use crate::service::{action_router, list_all_dbs, search_all_dbs, transfer_router, Federated};
 use crate::config::config_router;
-use crate::domain::{Action, Setup, Todo, TodoResponse};
+use crate::config_file::is_optional_key;
+use crate::dates::{parse_due, parse_since};
+use crate::domain::{
+    check_db_name, Action, ConfigurationArgument, DbSummary, Filter, Priority, Search, Setup,
+    Status, Todo, TodoError, TodoErrorType, TodoResponse, ID,
+};
+use crate::editor::{edit_file, edit_todo};
+use crate::output::{Format, TodoWriter};
+use crate::paths::Paths;
+use crate::store::Change;
 
 const DELIMETER: &str = "$";
+const END_MARKER: &str = ".";
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const HIGHLIGHT: (&str, &str) = ("\x1b[1;33m", "\x1b[0m");
+const PLAIN_HIGHLIGHT: (&str, &str) = ("[", "]");
 
 cfg_if! {
     if #[cfg(test)] {
@@ -18,13 +39,24 @@ cfg_if! {
     }
 }
 
-pub fn handle_config_argument(matches: &ArgMatches) -> Settings {
-    let base_settings = match Settings::load_config() {
+pub fn handle_config_argument(matches: &ArgMatches) -> Result<Settings, TodoError> {
+    let paths = Paths::resolve(matches.value_of("config"));
+    if let Some(migrate) = matches.subcommand_matches("migrate") {
+        if migrate.is_present("from-cwd") {
+            migrate_working_dir(&paths)?;
+        }
+    }
+    let base_settings = match Settings::load_config(paths.clone()) {
         Ok(settings) => settings,
-        Err(why) => {
+        Err(why) if why.is(TodoErrorType::InitNotAvailable) => {
             info!("Unable to load configuraiton, setting default : {}", why);
-            Settings::system_default()
+            Settings::system_default(paths)
+        }
+        Err(why) if why.is(TodoErrorType::InvalidInput) && repairs_config(matches) => {
+            warn!("Using the default configuration : {}", why);
+            Settings::system_default(paths)
         }
+        Err(why) => return Err(why),
     };
     if matches.is_present("db") {
         let db = matches
@@ -32,140 +64,1090 @@ pub fn handle_config_argument(matches: &ArgMatches) -> Settings {
             .unwrap_or(base_settings.get_db().as_str())
             .trim()
             .to_lowercase();
-        base_settings.update(db)
+        Ok(base_settings.update(db))
     } else {
-        base_settings
+        Ok(base_settings)
     }
 }
 
-pub fn handle_init(matches: &ArgMatches, settings: &Settings) {
-    if matches.is_present("init") {
-        match config_router(settings, Setup::Init) {
-            Ok(_) => println!("Initialization completed successful"),
-            Err(why) => println!("Initialization has failed - Reason : {}", why),
+/// Replaces an alias of the configuration given in place of the command with the
+/// words of its command, so `xcon ls` runs `xcon list --format plain` when the alias
+/// is `ls = "list --format plain"`. Commands are never shadowed by aliases
+pub fn expand_alias(app: &App, args: Vec<OsString>) -> Vec<OsString> {
+    let takes_value = |arg: &str| {
+        app.get_arguments().any(|option| {
+            option.is_set(ArgSettings::TakesValue)
+                && match arg.strip_prefix("--") {
+                    Some(long) => option.get_long() == Some(long),
+                    None => option
+                        .get_short()
+                        .is_some_and(|short| arg[1..].chars().eq(Some(short))),
+                }
+        })
+    };
+    let mut config = None;
+    let mut position = 1;
+    while let Some(arg) = args.get(position).and_then(|arg| arg.to_str()) {
+        if !arg.starts_with('-') {
+            break;
+        }
+        if arg == "--config" {
+            config = args.get(position + 1).and_then(|path| path.to_str());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(path);
+        }
+        position += if takes_value(arg) { 2 } else { 1 };
+    }
+    let name = match args.get(position).and_then(|arg| arg.to_str()) {
+        Some(name)
+            if app
+                .get_subcommands()
+                .all(|command| command.get_name() != name) =>
+        {
+            name
         }
+        _ => return args,
+    };
+    let alias = Settings::load_config(Paths::resolve(config))
+        .ok()
+        .and_then(|settings| settings.get_alias(name));
+    match alias {
+        Some(words) => {
+            let mut expanded = args[..position].to_vec();
+            expanded.extend(words.into_iter().map(OsString::from));
+            expanded.extend_from_slice(&args[position + 1..]);
+            expanded
+        }
+        None => args,
     }
 }
 
-pub fn handle_test(matches: &ArgMatches, settings: &Settings) {
-    if matches.is_present("test") {
-        match config_router(settings, Setup::Test) {
-            Ok(_) => println!("Test completed successful"),
-            Err(_) => println!("Test has failed, Please initalize"),
+/// Moves the config and the database earlier versions kept in the working directory,
+/// before the settings are read from their new location
+fn migrate_working_dir(paths: &Paths) -> Result<(), TodoError> {
+    let dir =
+        env::current_dir().map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+    let moved = paths
+        .migrate_from(&dir)
+        .map_err(|why| why.context("Unable to move the config out of the working directory"))?;
+    for (from, to) in moved {
+        println!("Moved {} to {}", from.display(), to.display());
+    }
+    Ok(())
+}
+
+/// Keeps the global flags passed with `--set` as defaults and reverts those named
+/// by `--unset`, printing every key written
+pub fn handle_set(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if !matches.is_present("set") && !matches.is_present("unset") {
+        return Ok(());
+    }
+    let argument = read_configuration_argument(matches)?;
+    let response = config_router(settings, Setup::Configure(argument))
+        .map_err(|why| why.context("Unable to update the config"))?;
+    if let TodoResponse::Configured(changes) = response {
+        for change in changes {
+            eprintln!("{}", change);
         }
     }
+    Ok(())
 }
 
-pub fn handle_add(matches: &ArgMatches, settings: &Settings) {
-    if let Some(_) = matches.subcommand_matches("add") {
-        let (title, content) = read_add_input();
-        let todo = Todo {
-            id: Option::None,
-            title,
-            content,
-            user_name: Option::None,
-        };
-        match action_router(&settings, Action::Save(todo)) {
-            Ok(_) => println!("Saved successful"),
-            Err(_) => println!("Save has failed, Please use test command"),
+fn read_configuration_argument(matches: &ArgMatches) -> Result<ConfigurationArgument, TodoError> {
+    let format = matches
+        .value_of("format")
+        .map(str::parse)
+        .transpose()
+        .map_err(TodoError::invalid_input)?;
+    Ok(ConfigurationArgument {
+        config: matches.value_of("config").map(str::to_owned),
+        db: matches.value_of("db").map(|db| db.trim().to_lowercase()),
+        format,
+        set: matches.is_present("set"),
+        unset: matches
+            .values_of("unset")
+            .map(|flags| flags.map(str::to_owned).collect())
+            .unwrap_or_default(),
+    })
+}
+
+pub fn handle_config(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    let config_matches = match matches.subcommand_matches("config") {
+        Some(config_matches) => config_matches,
+        None => return Ok(()),
+    };
+    match config_matches.subcommand() {
+        Some(("get", args)) => {
+            let key = args.value_of("key").unwrap_or_default();
+            let flags = read_configuration_argument(matches)?;
+            let entry = settings
+                .config_entries(&flags)
+                .into_iter()
+                .find(|entry| entry.key == key);
+            match entry {
+                Some(entry) => println!("{}", entry.raw_value()),
+                None if is_optional_key(key) => {
+                    return Err(TodoError::with_message(
+                        TodoErrorType::RecordNotFound,
+                        format!("`{}` is not set", key),
+                    ))
+                }
+                None => {
+                    return Err(TodoError::invalid_input(format!(
+                        "Unknown config key `{}`",
+                        key
+                    )))
+                }
+            }
+        }
+        Some(("set", args)) => {
+            let key = args.value_of("key").unwrap_or_default();
+            let value = args.value_of("value").unwrap_or_default();
+            let response = settings
+                .set_config(key, value)
+                .map_err(|why| why.context("Unable to update the config"))?;
+            if let TodoResponse::Configured(changes) = response {
+                for change in changes {
+                    println!("{}", change);
+                }
+            }
+        }
+        Some(("list", args)) => {
+            let flags = read_configuration_argument(matches)?;
+            for entry in settings.config_entries(&flags) {
+                if args.is_present("show-origin") {
+                    println!("{:<8} {}", entry.origin, entry);
+                } else {
+                    println!("{}", entry);
+                }
+            }
+        }
+        Some(("edit", _)) => {
+            let path = settings.write_config_file()?;
+            edit_file(&path, settings.get_editor())
+                .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+            settings.validate_config()?;
+            println!("Config {} is valid", path.display());
         }
+        Some(("validate", _)) => {
+            settings.validate_config()?;
+            println!("Config {} is valid", settings.get_config_file().display());
+        }
+        _ => (),
     }
+    Ok(())
 }
-pub fn handle_list(matches: &ArgMatches, settings: &Settings) {
-    if let Some(matches) = matches.subcommand_matches("list") {
-        if let Some(id) = matches.value_of("input").map(|id| id.trim().parse::<i64>()) {
-            match id {
-                Ok(record_id) => {
-                    if let Ok(response) = action_router(&settings, Action::FetchById(record_id)) {
-                        match response {
-                            TodoResponse::One(todo) => {
-                                if let Some(record) = todo {
-                                    let serialized_todo = serde_json::to_string(&record).unwrap();
-                                    println!("{}", serialized_todo);
-                                } else {
-                                    println!("Record not found")
-                                }
-                            }
-                            _ => println!("Record not found"),
-                        }
-                    } else {
-                        //Todo
+
+pub fn handle_db(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    let db_matches = match matches.subcommand_matches("db") {
+        Some(db_matches) => db_matches,
+        None => return Ok(()),
+    };
+    let name = |args: &ArgMatches, key: &str| check_db_name(args.value_of(key).unwrap_or_default());
+    let response = match db_matches.subcommand() {
+        Some(("create", args)) => {
+            let db = name(args, "name")?;
+            settings
+                .create_db(&db)
+                .map_err(|why| why.context("Unable to create the database"))?;
+            println!("Created database {}", db);
+            None
+        }
+        Some(("rename", args)) => {
+            let (from, to) = (name(args, "from")?, name(args, "to")?);
+            let response = settings
+                .rename_db(&from, &to)
+                .map_err(|why| why.context("Unable to rename the database"))?;
+            println!("Renamed database {} to {}", from, to);
+            Some(response)
+        }
+        Some(("drop", args)) => {
+            let db = name(args, "name")?;
+            let message = format!("the database {} and every todo in it", db);
+            if !settings.get_confirm().required(true) || remove_confirmation(&message) {
+                settings
+                    .drop_db(&db)
+                    .map_err(|why| why.context("Unable to drop the database"))?;
+                println!("Dropped database {}", db);
+            }
+            None
+        }
+        Some(("use", args)) => {
+            let db = name(args, "name")?;
+            Some(
+                settings
+                    .use_db(&db)
+                    .map_err(|why| why.context("Unable to use the database"))?,
+            )
+        }
+        _ => {
+            print_databases(settings.list_dbs()?);
+            None
+        }
+    };
+    if let Some(TodoResponse::Configured(changes)) = response {
+        for change in changes {
+            println!("{}", change);
+        }
+    }
+    Ok(())
+}
+
+fn print_databases(databases: Vec<DbSummary>) {
+    if databases.is_empty() {
+        println!("Databases not found");
+        return;
+    }
+    let width = databases
+        .iter()
+        .map(|db| db.name.len())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+    println!(
+        "  {:<width$}  {:>6}  {:>10}",
+        "NAME",
+        "TODO",
+        "SIZE",
+        width = width
+    );
+    for db in databases {
+        let todo = db
+            .todo
+            .map_or_else(|| "?".to_owned(), |todo| todo.to_string());
+        println!(
+            "{} {:<width$}  {:>6}  {:>10}",
+            if db.default { '*' } else { ' ' },
+            db.name,
+            todo,
+            format_size(db.size),
+            width = width
+        );
+    }
+}
+
+/// Size in bytes, or in the largest binary unit keeping it above one
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Whether the command repairs the config, so it runs with an invalid one
+fn repairs_config(matches: &ArgMatches) -> bool {
+    let command = matches
+        .subcommand_matches("config")
+        .and_then(|matches| matches.subcommand_name());
+    matches!(command, Some("edit") | Some("validate"))
+}
+
+pub fn handle_init(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if matches.is_present("init") {
+        config_router(settings, Setup::Init)
+            .map_err(|why| why.context("Initialization has failed"))?;
+        println!("Initialization completed successful");
+    }
+    Ok(())
+}
+
+pub fn handle_test(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if matches.is_present("test") {
+        config_router(settings, Setup::Test).map_err(|why| why.context("Test has failed"))?;
+        println!("Test completed successful");
+    }
+    Ok(())
+}
+
+pub fn handle_migrate(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let preview = matches.is_present("status") || matches.is_present("dry-run");
+        let setup = if preview {
+            Setup::MigrationStatus
+        } else {
+            Setup::Migrate
+        };
+        match config_router(settings, setup).map_err(|why| why.context("Migration has failed"))? {
+            TodoResponse::Schema(version) => {
+                if matches.is_present("status") {
+                    println!(
+                        "Schema version {}, latest version {}",
+                        version.current, version.latest
+                    );
+                }
+                if version.pending.is_empty() {
+                    println!("Schema is up to date");
+                } else {
+                    match (preview, matches.is_present("dry-run")) {
+                        (false, _) => println!("Applied migrations"),
+                        (true, true) => println!("Migrations to apply"),
+                        (true, false) => println!("Pending migrations"),
+                    }
+                    for (version, description) in version.pending {
+                        println!("{:>4}  {}", version, description);
                     }
                 }
-                Err(_) => println!("Not a valid integer"),
+            }
+            _ => println!("Schema is up to date"),
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_add(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("add") {
+        let todo = read_new_todo(matches)?;
+        if todo.title.is_empty() {
+            return Err(TodoError::invalid_input(
+                "Title can not be empty, todo is not saved",
+            ));
+        }
+        action_router(settings, Action::Save(todo))
+            .map_err(|why| why.context("Save has failed"))?;
+        println!("Saved successful");
+    }
+    Ok(())
+}
+
+fn read_new_todo(matches: &ArgMatches) -> Result<Todo, TodoError> {
+    let tags = read_tags(matches, "tag").map_err(TodoError::invalid_input)?;
+    let due = read_due(matches, "due").map_err(TodoError::invalid_input)?;
+    let priority = read_priority(matches).map_err(TodoError::invalid_input)?;
+    let (title, content) = read_add_input(matches).map_err(|why| {
+        TodoError::io(TodoErrorType::InvalidInput, why).context("Unable to read input")
+    })?;
+    Ok(Todo {
+        tags,
+        due,
+        priority,
+        ..Todo::new(title, content)
+    })
+}
+
+pub fn handle_list(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let writer = read_writer(matches, settings).map_err(TodoError::invalid_input)?;
+        if let Some(id) = read_id(matches)? {
+            match action_router(settings, Action::FetchById(id))
+                .map_err(|why| why.context("Unable to find record"))?
+            {
+                TodoResponse::One(Some(record)) => print_todo(&record, writer),
+                _ => return Err(TodoError::build(TodoErrorType::RecordNotFound)),
             }
         } else {
-            if let Ok(response) = action_router(&settings, Action::Fetch) {
-                match response {
-                    TodoResponse::All(todos) => {
-                        for todo in todos {
-                            let serialized_todo = serde_json::to_string(&todo).unwrap();
-                            println!("{}", serialized_todo);
-                        }
-                    }
-                    _ => println!("Records not found"),
+            let filter = read_filter(matches).map_err(TodoError::invalid_input)?;
+            if matches.is_present("all-dbs") {
+                list_every_db(&filter, writer, settings)?;
+            } else {
+                list_all(filter, writer, settings)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_filter(matches: &ArgMatches) -> Result<Filter, String> {
+    let status = if matches.is_present("all") {
+        None
+    } else if matches.is_present("done") {
+        Some(Status::Done)
+    } else {
+        Some(Status::Open)
+    };
+    let (limit, offset) = read_page(matches)?;
+    let mut due_before = read_due(matches, "due-before")?;
+    if matches.is_present("overdue") {
+        let now = Utc::now();
+        due_before = Some(due_before.map_or(now, |due| due.min(now)));
+    }
+    Ok(Filter {
+        tags: read_tags(matches, "tag")?,
+        any_tag: matches.is_present("any"),
+        status,
+        due_before,
+        created_since: matches
+            .value_of("since")
+            .map(|since| parse_since(since, Local::now()))
+            .transpose()?,
+        created_until: read_due(matches, "until")?,
+        sort: matches
+            .value_of("sort")
+            .map(|sort| sort.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        reverse: matches.is_present("reverse"),
+        limit,
+        offset,
+    })
+}
+
+/// Reads `--limit` with either `--offset` or the 1-based `--page` of `--limit` sized pages
+fn read_page(matches: &ArgMatches) -> Result<(Option<i64>, i64), String> {
+    let number = |name: &str| {
+        matches
+            .value_of(name)
+            .map(|value| match value.trim().parse::<i64>() {
+                Ok(number) if number >= 0 => Ok(number),
+                _ => Err(format!("Not a valid {} '{}'", name, value)),
+            })
+            .transpose()
+    };
+    let limit = number("limit")?;
+    let offset = match (number("offset")?, number("page")?) {
+        (_, Some(0)) => return Err("Not a valid page '0', pages start at 1".to_owned()),
+        (_, Some(page)) => (page - 1) * limit.unwrap_or_default(),
+        (offset, None) => offset.unwrap_or_default(),
+    };
+    Ok((limit, offset))
+}
+
+/// Writer for `--template`, either a template named in the configuration or the
+/// template itself, otherwise for the global `--format` which defaults to the
+/// configured format, or a table at a terminal and NDJSON when piped
+fn read_writer(matches: &ArgMatches, settings: &Settings) -> Result<TodoWriter<Stdout>, String> {
+    let urgency = settings.get_urgency();
+    if let Some(template) = matches.value_of("template") {
+        let template = match settings.get_template(template) {
+            Some(named) => named,
+            None if template.contains('{') => template.to_owned(),
+            None => {
+                return Err(format!(
+                    "No template named '{}' in the configuration",
+                    template
+                ))
+            }
+        };
+        return Ok(TodoWriter::with_template(
+            stdout(),
+            template.parse()?,
+            urgency,
+        ));
+    }
+    let format = matches
+        .value_of("format")
+        .map(|format| format.parse())
+        .or_else(|| settings.get_format().map(Ok))
+        .unwrap_or_else(|| Ok(Format::default_for(stdout().is_terminal())))?;
+    Ok(TodoWriter::new(stdout(), format, urgency).with_date_format(settings.get_date_format()))
+}
+
+fn print_todo(todo: &Todo, mut writer: TodoWriter<Stdout>) {
+    let _ = writer.write(todo).and_then(|_| writer.finish());
+}
+
+/// Prints todo as they are read from the store, stops quietly once stdout is closed
+fn list_all(
+    filter: Filter,
+    writer: TodoWriter<Stdout>,
+    settings: &Settings,
+) -> Result<(), TodoError> {
+    let writer = Rc::new(RefCell::new(writer));
+    let sink = {
+        let writer = Rc::clone(&writer);
+        Box::new(move |todo: Todo| writer.borrow_mut().write(&todo).is_ok())
+    };
+    let response = action_router(settings, Action::Stream(filter, sink));
+    let written = writer.borrow_mut().finish();
+    response.map_err(|why| why.context("Unable to list todo"))?;
+    if let Ok(0) = written {
+        if writer.borrow().is_human() {
+            println!("Records not found");
+        }
+    }
+    Ok(())
+}
+
+/// Lists the todo of every database, each named in a column of its own
+fn list_every_db(
+    filter: &Filter,
+    writer: TodoWriter<Stdout>,
+    settings: &Settings,
+) -> Result<(), TodoError> {
+    let todos = list_all_dbs(settings, filter)
+        .map(skip_failed)
+        .map_err(|why| why.context("Unable to list todo"))?;
+    let mut writer = writer.with_db_column();
+    for (db, todo) in &todos {
+        if writer.write_from(db, todo).is_err() {
+            break;
+        }
+    }
+    if let Ok(0) = writer.finish() {
+        if writer.is_human() {
+            println!("Records not found");
+        }
+    }
+    Ok(())
+}
+
+/// Warns about the databases that could not be read and keeps what the others had
+fn skip_failed<T>((found, failed): Federated<T>) -> Vec<(String, T)> {
+    for (db, why) in failed {
+        eprintln!("Skipping the database {} : {}", db, why);
+    }
+    found
+}
+
+pub fn handle_next(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("next") {
+        let writer = read_writer(matches, settings).map_err(TodoError::invalid_input)?;
+        let filter = Filter {
+            status: Some(Status::Open),
+            limit: Some(1),
+            ..Filter::default()
+        };
+        match action_router(settings, Action::Fetch(filter))
+            .map_err(|why| why.context("Unable to find next todo"))?
+        {
+            TodoResponse::All(todos) if !todos.is_empty() => print_todo(&todos[0], writer),
+            _ => println!("Nothing to do, no open todo found"),
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_search(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("search") {
+        let query = matches.values_of("query").into_iter().flatten();
+        let limit = matches
+            .value_of("limit")
+            .map(|limit| limit.trim().parse::<i64>())
+            .unwrap_or(Ok(DEFAULT_SEARCH_LIMIT))
+            .map_err(|_| TodoError::invalid_input("Not a valid integer"))?;
+        let (open, close) = if settings.get_color().enabled(stdout().is_terminal()) {
+            HIGHLIGHT
+        } else {
+            PLAIN_HIGHLIGHT
+        };
+        let search = Search {
+            query: query.collect::<Vec<&str>>().join(" "),
+            limit,
+            markers: (open.to_owned(), close.to_owned()),
+        };
+        let hits = if matches.is_present("all-dbs") {
+            search_all_dbs(settings, &search).map(skip_failed)
+        } else {
+            action_router(settings, Action::Search(search)).map(|response| match response {
+                TodoResponse::Hits(hits) => {
+                    hits.into_iter().map(|hit| (String::new(), hit)).collect()
                 }
+                _ => Vec::new(),
+            })
+        }
+        .map_err(|why| {
+            if why.is(TodoErrorType::InvalidQuery) {
+                why.context("Use words, \"phrases\", prefix* and AND/OR/NOT")
+            } else {
+                why.context("Search has failed")
+            }
+        })?;
+        if hits.is_empty() {
+            println!("Records not found");
+        }
+        for (db, hit) in hits {
+            let id = hit.todo.id.unwrap_or_default();
+            let id = if db.is_empty() {
+                id.to_string()
             } else {
-                //TODO
+                format!("{}:{}", db, id)
+            };
+            println!("{:>4}  {} ({})", id, hit.title, hit.todo.status.as_str());
+            if !hit.snippet.is_empty() {
+                println!("      {}", hit.snippet.replace('\n', " "));
             }
         }
     }
+    Ok(())
 }
 
-pub fn handle_remove(matches: &ArgMatches, settings: &Settings) {
-    if let Some(matches) = matches.subcommand_matches("remove") {
-        if let Some(id) = matches.value_of("input").map(|id| id.trim().parse::<i64>()) {
-            match id {
-                Ok(record_id) => {
-                    let message = format!("a record id : {}", record_id);
-                    if remove_confirmation(&message) {
-                        if let Ok(response) = action_router(settings, Action::DeleteById(record_id))
-                        {
-                            match response {
-                                TodoResponse::Done => {
-                                    println!("Successfuly removed a record id {}", record_id)
-                                }
-                                _ => println!("Record not found"),
-                            }
-                        } else {
-                            //TODO
-                        }
+pub fn handle_edit(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("edit") {
+        if let Some(id) = read_id(matches)? {
+            match action_router(settings, Action::FetchById(id))
+                .map_err(|why| why.context("Edit has failed"))?
+            {
+                TodoResponse::One(Some(record)) => edit_record(record, matches, settings)?,
+                _ => return Err(TodoError::build(TodoErrorType::RecordNotFound)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the field flags when any is given, otherwise opens the record in the editor
+fn edit_record(record: Todo, matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    let todo = if ["tag", "untag", "due", "priority"]
+        .iter()
+        .any(|name| matches.is_present(name))
+    {
+        edit_fields(&record, matches).map_err(TodoError::invalid_input)?
+    } else {
+        edit_todo(&record.title, &record.content, settings.get_editor())
+            .map(|(title, content)| Todo {
+                title,
+                content,
+                ..record.clone()
+            })
+            .map_err(|why| {
+                TodoError::io(TodoErrorType::InvalidInput, why).context("Unable to open editor")
+            })?
+    };
+    if todo == record {
+        println!(
+            "No changes, record id {} is not updated",
+            record.id.unwrap_or_default()
+        );
+    } else if todo.title.is_empty() {
+        return Err(TodoError::invalid_input(
+            "Title can not be empty, record is not updated",
+        ));
+    } else {
+        action_router(settings, Action::Update(todo))
+            .map_err(|why| why.context("Update has failed"))?;
+        println!("Updated successful");
+    }
+    Ok(())
+}
+
+fn edit_fields(record: &Todo, matches: &ArgMatches) -> Result<Todo, String> {
+    let mut todo = record.clone();
+    for tag in read_tags(matches, "tag")? {
+        if !todo.tags.contains(&tag) {
+            todo.tags.push(tag);
+        }
+    }
+    let untag = read_tags(matches, "untag")?;
+    todo.tags.retain(|tag| !untag.contains(tag));
+    todo.tags.sort();
+    match matches.value_of("due") {
+        Some(due) if due.trim().eq_ignore_ascii_case("none") => todo.due = None,
+        Some(_) => todo.due = read_due(matches, "due")?,
+        None => (),
+    }
+    match matches.value_of("priority") {
+        Some(priority) if priority.trim().eq_ignore_ascii_case("none") => todo.priority = None,
+        Some(_) => todo.priority = read_priority(matches)?,
+        None => (),
+    }
+    Ok(todo)
+}
+
+pub fn handle_tags(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("tags") {
+        let merge = match matches.subcommand() {
+            Some(("rename", matches)) => {
+                Some((read_tags(matches, "from"), read_tags(matches, "to")))
+            }
+            Some(("merge", matches)) => {
+                Some((read_tags(matches, "sources"), read_tags(matches, "into")))
+            }
+            _ => None,
+        };
+        match merge {
+            Some((Ok(sources), Ok(target))) => merge_tags(sources, target.join(""), settings)?,
+            Some((Err(why), _)) | Some((_, Err(why))) => return Err(TodoError::invalid_input(why)),
+            None => match action_router(settings, Action::FetchTags)
+                .map_err(|why| why.context("Unable to list tags"))?
+            {
+                TodoResponse::Tags(tags) if !tags.is_empty() => {
+                    for tag in tags {
+                        println!("{:<24} {}", tag.name, tag.count);
                     }
                 }
-                Err(_) => println!("Not a valid integer"),
-            }
+                _ => println!("Tags not found"),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn merge_tags(sources: Vec<String>, target: String, settings: &Settings) -> Result<(), TodoError> {
+    let message = format!("{} into {}", sources.join(", "), target);
+    action_router(settings, Action::MergeTags(sources, target)).map_err(|why| {
+        if why.is(TodoErrorType::RecordNotFound) {
+            TodoError::with_message(TodoErrorType::RecordNotFound, "Tag not found")
         } else {
-            if remove_confirmation("all records") {
-                if let Ok(response) = action_router(settings, Action::Delete) {
-                    match response {
-                        TodoResponse::Done => println!("Remove all successful "),
-                        _ => println!("Record not found"),
-                    }
+            why.context("Merge has failed")
+        }
+    })?;
+    println!("Successfuly merged tags {}", message);
+    Ok(())
+}
+
+pub fn handle_done(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("done") {
+        change_status(matches, settings, Status::Done)?;
+    }
+    Ok(())
+}
+
+pub fn handle_reopen(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("reopen") {
+        change_status(matches, settings, Status::Open)?;
+    }
+    Ok(())
+}
+
+fn change_status(
+    matches: &ArgMatches,
+    settings: &Settings,
+    status: Status,
+) -> Result<(), TodoError> {
+    if let Some(record_id) = read_id(matches)? {
+        let context = |why: TodoError| why.context("Status update has failed");
+        match action_router(settings, Action::FetchById(record_id)).map_err(context)? {
+            TodoResponse::One(Some(record)) if record.status == status => {
+                println!("Record id {} is already {}", record_id, status.as_str())
+            }
+            TodoResponse::One(Some(_)) => {
+                let action = match status {
+                    Status::Done => Action::Complete(record_id),
+                    Status::Open => Action::Reopen(record_id),
+                };
+                action_router(settings, action).map_err(context)?;
+                println!("Record id {} is now {}", record_id, status.as_str());
+            }
+            _ => return Err(TodoError::build(TodoErrorType::RecordNotFound)),
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_remove(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("remove") {
+        let record_ids = read_ids(matches)?;
+        let confirm = settings.get_confirm();
+        if let [record_id] = record_ids[..] {
+            let message = format!("a record id : {}", record_id);
+            if !confirm.required(false) || remove_confirmation(&message) {
+                action_router(settings, Action::DeleteById(record_id))
+                    .map_err(|why| why.context("Remove has failed"))?;
+                println!("Successfuly removed a record id {}", record_id);
+            }
+        } else if !record_ids.is_empty() {
+            let listed: Vec<String> = record_ids.iter().map(ID::to_string).collect();
+            let message = format!("record ids : {}", listed.join(", "));
+            if !confirm.required(true) || remove_confirmation(&message) {
+                let changes = record_ids.into_iter().map(Change::Remove).collect();
+                action_router(settings, Action::Batch(changes))
+                    .map_err(|why| why.context("Remove has failed"))?;
+                println!("Successfuly removed record ids {}", listed.join(", "));
+            }
+        } else if !confirm.required(true) || remove_confirmation("all records") {
+            action_router(settings, Action::Delete)
+                .map_err(|why| why.context("Remove has failed"))?;
+            println!("Remove all successful ");
+        }
+    }
+    Ok(())
+}
+
+/// Reads the record id given with `-i`, if any
+fn read_id(matches: &ArgMatches) -> Result<Option<i64>, TodoError> {
+    matches.value_of("input").map(parse_id).transpose()
+}
+
+/// Reads every record id given with `-i`, without duplicates
+fn read_ids(matches: &ArgMatches) -> Result<Vec<ID>, TodoError> {
+    let mut ids = Vec::new();
+    for id in matches.values_of("input").into_iter().flatten() {
+        let id = parse_id(id)?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+fn parse_id(id: &str) -> Result<ID, TodoError> {
+    id.trim()
+        .parse::<ID>()
+        .map_err(|_| TodoError::invalid_input("Not a valid integer"))
+}
+
+pub fn handle_move(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("move") {
+        transfer_todo(matches, settings, true)?;
+    }
+    Ok(())
+}
+
+pub fn handle_copy(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("copy") {
+        transfer_todo(matches, settings, false)?;
+    }
+    Ok(())
+}
+
+/// Moves or copies the todo into the database given with `--to` and prints its old
+/// and new id
+fn transfer_todo(matches: &ArgMatches, settings: &Settings, remove: bool) -> Result<(), TodoError> {
+    let (verb, failure) = if remove {
+        ("Moved", "Move has failed")
+    } else {
+        ("Copied", "Copy has failed")
+    };
+    let record_id = read_id(matches)?.unwrap_or_default();
+    let target = settings.update(check_db_name(matches.value_of("to").unwrap_or_default())?);
+    let response = transfer_router(settings, &target, record_id, remove)
+        .map_err(|why| why.context(failure))?;
+    if let TodoResponse::Transferred(new_id) = response {
+        println!(
+            "{} {}:{} -> {}:{}",
+            verb,
+            settings.get_db_name(),
+            record_id,
+            target.get_db_name(),
+            new_id
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_import(matches: &ArgMatches, settings: &Settings) -> Result<(), TodoError> {
+    if let Some(matches) = matches.subcommand_matches("import") {
+        let text = match matches.value_of("file") {
+            Some(path) => fs::read_to_string(path),
+            None => {
+                let mut piped = String::new();
+                stdin().read_to_string(&mut piped).map(|_| piped)
+            }
+        }
+        .map_err(|why| {
+            TodoError::io(TodoErrorType::InvalidInput, why).context("Unable to read input")
+        })?;
+        let changes = parse_import(&text)
+            .map_err(|why| TodoError::invalid_input(why).context("Nothing was imported"))?
+            .into_iter()
+            .map(Change::Save)
+            .collect();
+        match action_router(settings, Action::Batch(changes))
+            .map_err(|why| why.context("Import has failed, nothing was imported"))?
+        {
+            TodoResponse::Applied(count) => println!("Imported {} todo", count),
+            _ => println!("Import successful"),
+        }
+    }
+    Ok(())
+}
+
+/// Reads todo in the schema of `--format json`, as one array or one object per line.
+/// Every todo gets a new id, status and timestamps are kept
+fn parse_import(text: &str) -> Result<Vec<Todo>, String> {
+    let todos: Vec<Todo> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(text).map_err(|why| format!("Not valid JSON : {}", why))?
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|why| format!("Line {} : {}", number + 1, why))
+            })
+            .collect::<Result<Vec<Todo>, String>>()?
+    };
+    todos
+        .into_iter()
+        .enumerate()
+        .map(|(number, todo)| {
+            let title = todo.title.trim().to_owned();
+            if title.is_empty() {
+                return Err(format!("Todo {} has an empty title", number + 1));
+            }
+            let mut tags = Vec::new();
+            for tag in &todo.tags {
+                let tag = clean_tag(tag)?;
+                if !tags.contains(&tag) {
+                    tags.push(tag);
                 }
-            } else {
-                //TODO : ignore
             }
+            Ok(Todo {
+                id: None,
+                title,
+                tags,
+                ..todo
+            })
+        })
+        .collect()
+}
+
+/// Prints the error on stderr, as `context - Reason : error : cause` or, with
+/// `--error-format json`, as one JSON object with the error category and exit code
+pub fn report_error(matches: &ArgMatches, why: &TodoError) {
+    let json = error_format(matches) == Some("json");
+    eprintln!("{}", render_error(why, json));
+}
+
+/// The error with its context and causes, as one line of text or one JSON object
+fn render_error(why: &TodoError, json: bool) -> String {
+    let mut causes = Vec::new();
+    let mut cause = why.source();
+    while let Some(source) = cause {
+        causes.push(source.to_string());
+        cause = source.source();
+    }
+    if json {
+        let report = ErrorReport {
+            error: why.error_type(),
+            code: why.exit_code(),
+            message: why.to_string(),
+            context: why.get_context(),
+            causes,
+        };
+        serde_json::to_string(&report).unwrap_or_else(|_| why.to_string())
+    } else {
+        let reason = std::iter::once(why.to_string())
+            .chain(causes)
+            .collect::<Vec<String>>()
+            .join(" : ");
+        match why.get_context() {
+            Some(context) => format!("{} - Reason : {}", context, reason),
+            None => reason,
+        }
+    }
+}
+
+/// Error as printed by `--error-format json`
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    error: TodoErrorType,
+    code: i32,
+    message: String,
+    context: Option<&'a str>,
+    causes: Vec<String>,
+}
+
+/// The global `--error-format`, which may be given after any of the subcommands
+fn error_format(matches: &ArgMatches) -> Option<&str> {
+    matches.value_of("error-format").or_else(|| {
+        matches
+            .subcommand()
+            .and_then(|(_, matches)| error_format(matches))
+    })
+}
+
+/// Resolves title and content from `--title`, `--content` and `--content-file`.
+/// Missing values are prompted for on a terminal, otherwise they are read from piped stdin
+fn read_add_input(matches: &ArgMatches) -> io::Result<(String, String)> {
+    let title = matches
+        .value_of("title")
+        .map(|title| title.trim().to_owned());
+    let content = match (
+        matches.value_of("content"),
+        matches.value_of("content-file"),
+    ) {
+        (Some(content), _) => Some(content.to_owned()),
+        (None, Some(path)) => Some(fs::read_to_string(path)?),
+        (None, None) => None,
+    };
+    let (title, content) = if stdin().is_terminal() {
+        let title = match title {
+            Some(title) => title,
+            None => read_line("Title")?,
+        };
+        let content = match content {
+            Some(content) => content,
+            None => read_multi_line("Content")?,
+        };
+        (title, content)
+    } else {
+        let mut piped = String::new();
+        if title.is_none() || content.is_none() {
+            stdin().read_to_string(&mut piped)?;
+        }
+        split_piped(title, content, piped)
+    };
+    Ok((
+        title,
+        content.trim_end_matches(&['\n', '\r'][..]).to_owned(),
+    ))
+}
+
+/// Takes what the flags left out from piped input, the title from its first line
+/// and the content from the rest, or all of it when only the content is missing
+fn split_piped(title: Option<String>, content: Option<String>, piped: String) -> (String, String) {
+    match (title, content) {
+        (Some(title), Some(content)) => (title, content),
+        (Some(title), None) => (title, piped),
+        (None, content) => {
+            let mut lines = piped.splitn(2, '\n');
+            let title = lines.next().unwrap_or_default().trim().to_owned();
+            let rest = lines.next().unwrap_or_default().to_owned();
+            (title, content.unwrap_or(rest))
         }
     }
 }
-fn read_add_input() -> (String, String) {
-    let mut title = String::new();
-    let mut content = String::new();
-    print!("Title {} ", DELIMETER);
-    let _ = stdout().flush();
-    stdin()
-        .read_line(&mut title)
-        .expect("Did not enter a correct string");
-    clean_input(&mut title);
 
-    print!("Content {} ", DELIMETER);
+fn read_line(label: &str) -> io::Result<String> {
+    let mut line = String::new();
+    print!("{} {} ", label, DELIMETER);
     let _ = stdout().flush();
-    stdin()
-        .read_line(&mut content)
-        .expect("Did not enter a correct string");
-    clean_input(&mut content);
-    (title, content)
+    stdin().read_line(&mut line)?;
+    clean_input(&mut line);
+    Ok(line.trim().to_owned())
+}
+
+fn read_multi_line(label: &str) -> io::Result<String> {
+    println!(
+        "{} (finish with a line containing only '{}') {}",
+        label, END_MARKER, DELIMETER
+    );
+    read_until_marker(stdin().lock())
+}
+
+/// Lines up to the end marker or the end of input, without the marker
+fn read_until_marker(input: impl BufRead) -> io::Result<String> {
+    let mut lines = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim_end() == END_MARKER {
+            break;
+        }
+        lines.push(line);
+    }
+    Ok(lines.join("\n"))
+}
+/// Reads tag values of the argument, tags are lowercase words without commas
+fn read_tags(matches: &ArgMatches, name: &str) -> Result<Vec<String>, String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in matches.values_of(name).into_iter().flatten() {
+        let tag = clean_tag(tag)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Lowercase tag without commas or white space
+fn clean_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.contains(|c: char| c == ',' || c.is_whitespace()) {
+        return Err(format!("Not a valid tag '{}'", tag));
+    }
+    Ok(tag)
+}
+
+fn read_due(matches: &ArgMatches, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+    matches
+        .value_of(name)
+        .map(|due| parse_due(due, Local::now()))
+        .transpose()
+}
+
+fn read_priority(matches: &ArgMatches) -> Result<Option<Priority>, String> {
+    matches
+        .value_of("priority")
+        .map(|priority| priority.parse::<Priority>())
+        .transpose()
 }
+
 fn clean_input(s: &mut String) {
     if let Some('\n') = s.chars().next_back() {
         s.pop();
@@ -182,9 +1164,9 @@ fn remove_confirmation(message: &str) -> bool {
         message, DELIMETER
     );
     let _ = stdout().flush();
-    stdin()
-        .read_line(&mut confirmation)
-        .expect("Did not enter a correct string");
+    if stdin().read_line(&mut confirmation).is_err() {
+        return false;
+    }
     clean_input(&mut confirmation);
     if confirmation.eq_ignore_ascii_case("N") || confirmation.eq_ignore_ascii_case("n") {
         return false;
@@ -192,3 +1174,151 @@ fn remove_confirmation(message: &str) -> bool {
         return true;
     }
 }
+
+#[cfg(test)]
+fn parse_args(args: &[&str]) -> ArgMatches {
+    App::from(clap::load_yaml!("cli.yml")).get_matches_from(args)
+}
+
+#[test]
+fn expand_alias_test() {
+    let yaml = clap::load_yaml!("cli.yml");
+    let app = App::from(yaml);
+    let context = Settings::load_config_context();
+    context.expect().returning(|_| {
+        let mut settings = Settings::default();
+        settings.expect_get_alias().returning(|name| match name {
+            "ls" => Some(vec![
+                "list".to_owned(),
+                "--format".to_owned(),
+                "plain".to_owned(),
+            ]),
+            _ => None,
+        });
+        Ok(settings)
+    });
+    let expand = |args: &[&str]| {
+        expand_alias(&app, args.iter().map(OsString::from).collect())
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        expand(&["xcon", "--db", "work", "--set", "ls", "-a"]),
+        ["xcon", "--db", "work", "--set", "list", "--format", "plain", "-a"]
+    );
+    assert_eq!(
+        expand(&["xcon", "-d", "ls", "-s", "ls"]),
+        ["xcon", "-d", "ls", "-s", "list", "--format", "plain"]
+    );
+    assert_eq!(expand(&["xcon", "--db", "ls"]), ["xcon", "--db", "ls"]);
+    assert_eq!(expand(&["xcon", "list", "ls"]), ["xcon", "list", "ls"]);
+    assert_eq!(expand(&["xcon", "la"]), ["xcon", "la"]);
+}
+
+#[test]
+fn read_add_input_test() {
+    let matches = parse_args(&["xcon", "add", "-t", " Buy milk ", "-c", "milk\neggs\n"]);
+    let matches = matches.subcommand_matches("add").unwrap();
+    assert_eq!(
+        read_add_input(matches).unwrap(),
+        ("Buy milk".to_owned(), "milk\neggs".to_owned())
+    );
+
+    let path = env::temp_dir().join(format!("xcon-content-{}.txt", std::process::id()));
+    fs::write(&path, "from a file\r\n").unwrap();
+    let matches = parse_args(&["xcon", "add", "-t", "Read", "-f", path.to_str().unwrap()]);
+    let matches = matches.subcommand_matches("add").unwrap();
+    let read = read_add_input(matches);
+    let _ = fs::remove_file(&path);
+    assert_eq!(read.unwrap(), ("Read".to_owned(), "from a file".to_owned()));
+}
+
+#[test]
+fn split_piped_test() {
+    let piped = || " Groceries \nmilk\neggs\n".to_owned();
+    assert_eq!(
+        split_piped(None, None, piped()),
+        ("Groceries".to_owned(), "milk\neggs\n".to_owned())
+    );
+    assert_eq!(
+        split_piped(Some("Shop".to_owned()), None, piped()),
+        ("Shop".to_owned(), piped())
+    );
+    assert_eq!(
+        split_piped(None, Some("bread".to_owned()), piped()),
+        ("Groceries".to_owned(), "bread".to_owned())
+    );
+}
+
+#[test]
+fn read_until_marker_test() {
+    let input = "milk\n  eggs\n. \nnot read\n";
+    assert_eq!(read_until_marker(input.as_bytes()).unwrap(), "milk\n  eggs");
+    assert_eq!(
+        read_until_marker("milk\n.x\n".as_bytes()).unwrap(),
+        "milk\n.x"
+    );
+    assert_eq!(read_until_marker("".as_bytes()).unwrap(), "");
+}
+
+#[test]
+fn read_filter_status_test() {
+    let status = |args: &[&str]| {
+        let matches = parse_args(args);
+        read_filter(matches.subcommand_matches("list").unwrap())
+            .unwrap()
+            .status
+    };
+    assert_eq!(status(&["xcon", "list"]), Some(Status::Open));
+    assert_eq!(status(&["xcon", "list", "--done"]), Some(Status::Done));
+    assert_eq!(status(&["xcon", "list", "--all"]), None);
+    let conflict = App::from(clap::load_yaml!("cli.yml"))
+        .try_get_matches_from(["xcon", "list", "--all", "--done"]);
+    assert!(conflict.is_err());
+
+    let undo = parse_args(&["xcon", "undo", "-i", "2"]);
+    let reopen = undo.subcommand_matches("reopen").unwrap();
+    assert_eq!(read_id(reopen).unwrap(), Some(2));
+}
+
+#[test]
+fn render_error_test() {
+    let cause = io::Error::new(io::ErrorKind::NotFound, "no such file");
+    let why = TodoError::io(TodoErrorType::StorageFailure, cause).context("Save has failed");
+    assert_eq!(
+        render_error(&why, false),
+        "Save has failed - Reason : Unable to access the todo store : no such file"
+    );
+    assert_eq!(
+        render_error(&TodoError::invalid_input("Not a valid integer"), false),
+        "Not a valid integer"
+    );
+
+    let report: serde_json::Value = serde_json::from_str(&render_error(&why, true)).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "error": "storage_failure",
+            "code": 1,
+            "message": "Unable to access the todo store",
+            "context": "Save has failed",
+            "causes": ["no such file"]
+        })
+    );
+    let missing = TodoError::build(TodoErrorType::RecordNotFound);
+    let report: serde_json::Value = serde_json::from_str(&render_error(&missing, true)).unwrap();
+    assert_eq!(report["error"], "not_found");
+    assert_eq!(report["code"], 4);
+    assert_eq!(report["context"], serde_json::Value::Null);
+}
+
+#[test]
+fn error_format_test() {
+    let before = parse_args(&["xcon", "--error-format", "json", "list"]);
+    assert_eq!(error_format(&before), Some("json"));
+    let after = parse_args(&["xcon", "list", "--error-format", "json"]);
+    assert_eq!(error_format(&after), Some("json"));
+    assert_eq!(error_format(&parse_args(&["xcon", "list"])), None);
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::domain::{
+    Filter, Search, SearchHit, Status, Tag, Todo, TodoError, TodoErrorType, Urgency, ID,
+};
+use crate::lock::FileLock;
+use crate::memory::MemoryStore;
+use crate::store::{Change, NoteStore};
+
+/// Store kept in a single pretty printed JSON array ordered by id, so changes
+/// read well in a diff. The whole file is loaded on open and rewritten after
+/// every change through a temporary file renamed over it. The store is locked while
+/// open, so concurrent commands do not overwrite the changes of one another
+pub struct JsonStore {
+    path: PathBuf,
+    todos: MemoryStore,
+    _lock: FileLock,
+}
+
+impl JsonStore {
+    /// Creates an empty store unless the file is already there
+    pub fn create(path: &str) -> Result<(), TodoError> {
+        if Path::new(path).exists() {
+            return Ok(());
+        }
+        write_todos(Path::new(path), &[])
+    }
+
+    /// Counts the todo of the file, which is replaced in a single rename so it is read
+    /// without taking the lock
+    pub fn count(path: &str) -> Result<usize, TodoError> {
+        read_todos(Path::new(path)).map(|todos| todos.len())
+    }
+
+    pub fn open(path: &str) -> Result<Self, TodoError> {
+        let path = PathBuf::from(path);
+        let lock = FileLock::acquire(path.with_extension("json.lock"))?;
+        let todos = read_todos(&path)?;
+        Ok(Self {
+            path,
+            todos: MemoryStore::from_todos(todos),
+            _lock: lock,
+        })
+    }
+
+    fn persist<T>(&mut self, written: T) -> Result<T, TodoError> {
+        write_todos(&self.path, self.todos.todos())?;
+        Ok(written)
+    }
+}
+
+fn read_todos(path: &Path) -> Result<Vec<Todo>, TodoError> {
+    let text = fs::read_to_string(path).map_err(|why| {
+        if why.kind() == ErrorKind::NotFound {
+            TodoError::caused_by(TodoErrorType::InitNotAvailable, why)
+        } else {
+            TodoError::io(TodoErrorType::StorageFailure, why)
+        }
+    })?;
+    serde_json::from_str(&text).map_err(|why| {
+        TodoError::caused_by(TodoErrorType::StorageCorrupt, why)
+            .context(format!("Unable to read {}", path.display()))
+    })
+}
+
+fn write_todos(path: &Path, todos: &[Todo]) -> Result<(), TodoError> {
+    let mut json = serde_json::to_string_pretty(todos)
+        .map_err(|why| TodoError::caused_by(TodoErrorType::StorageFailure, why))?;
+    json.push('\n');
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, json)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))
+}
+
+impl NoteStore for JsonStore {
+    fn save(&mut self, todo: Todo) -> Result<ID, TodoError> {
+        let id = self.todos.save(todo)?;
+        self.persist(id)
+    }
+
+    fn find(&mut self, id: ID) -> Result<Option<Todo>, TodoError> {
+        self.todos.find(id)
+    }
+
+    fn update(&mut self, todo: Todo) -> Result<bool, TodoError> {
+        let updated = self.todos.update(todo)?;
+        self.persist(updated)
+    }
+
+    fn set_status(&mut self, id: ID, status: Status) -> Result<bool, TodoError> {
+        let updated = self.todos.set_status(id, status)?;
+        self.persist(updated)
+    }
+
+    fn remove(&mut self, id: ID) -> Result<bool, TodoError> {
+        let removed = self.todos.remove(id)?;
+        self.persist(removed)
+    }
+
+    fn remove_all(&mut self) -> Result<bool, TodoError> {
+        let removed = self.todos.remove_all()?;
+        self.persist(removed)
+    }
+
+    fn read_all(
+        &mut self,
+        filter: &Filter,
+        urgency: &Urgency,
+        sink: &mut dyn FnMut(Todo) -> bool,
+    ) -> Result<usize, TodoError> {
+        self.todos.read_all(filter, urgency, sink)
+    }
+
+    fn tags(&mut self) -> Result<Vec<Tag>, TodoError> {
+        self.todos.tags()
+    }
+
+    fn merge_tags(&mut self, sources: Vec<String>, target: String) -> Result<bool, TodoError> {
+        let merged = self.todos.merge_tags(sources, target)?;
+        self.persist(merged)
+    }
+
+    fn search(&mut self, search: &Search) -> Result<Vec<SearchHit>, TodoError> {
+        self.todos.search(search)
+    }
+
+    /// Writes the file once for the whole batch
+    fn apply(&mut self, changes: Vec<Change>) -> Result<usize, TodoError> {
+        let applied = self.todos.apply(changes)?;
+        self.persist(applied)
+    }
+
+    /// Rewrites the file and reads it back
+    fn check(&mut self) -> Result<(), TodoError> {
+        self.persist(())?;
+        if read_todos(&self.path)? == self.todos.todos() {
+            Ok(())
+        } else {
+            Err(TodoError::build(TodoErrorType::StorageCorrupt))
+        }
+    }
+}
+
+#[test]
+fn json_store_round_trip_test() {
+    let path = std::env::temp_dir().join(format!("xcon-json-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    assert!(JsonStore::open(path)
+        .err()
+        .unwrap()
+        .is(TodoErrorType::InitNotAvailable));
+
+    JsonStore::create(path).unwrap();
+    let mut store = JsonStore::open(path).unwrap();
+    store
+        .save(Todo::new("Buy milk".to_owned(), String::new()))
+        .unwrap();
+    store.check().unwrap();
+    drop(store);
+
+    let mut reopened = JsonStore::open(path).unwrap();
+    let found = reopened.find(1).unwrap().unwrap();
+    assert_eq!(found.title, "Buy milk");
+    assert!(found.created_at.is_some());
+
+    drop(reopened);
+    fs::write(path, "not json").unwrap();
+    assert!(JsonStore::open(path)
+        .err()
+        .unwrap()
+        .is(TodoErrorType::StorageCorrupt));
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(format!("{}.lock", path));
+}
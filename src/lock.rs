@@ -0,0 +1,141 @@
+use log::debug;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::domain::{TodoError, TodoErrorType};
+
+/// First delay between two attempts, doubled after every attempt
+const FIRST_DELAY: Duration = Duration::from_millis(10);
+/// Longest delay between two attempts
+const MAX_DELAY: Duration = Duration::from_millis(250);
+/// Time spent waiting on a busy resource before giving up, the time the attempts
+/// themselves take included. An attempt started before it may still wait out its
+/// own timeout, like the busy timeout of a SQLite connection
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delays between the attempts on a busy resource
+fn backoff() -> impl Iterator<Item = Duration> {
+    std::iter::successors(Some(FIRST_DELAY), |delay| Some((*delay * 2).min(MAX_DELAY)))
+}
+
+/// Runs the operation until it succeeds, fails for another reason than being busy
+/// or the timeout passes, in which case the last busy error is returned
+pub fn retry<T, E>(
+    operation: impl FnMut() -> Result<T, E>,
+    is_busy: fn(&E) -> bool,
+) -> Result<T, E> {
+    retry_until(Instant::now() + TIMEOUT, operation, is_busy)
+}
+
+/// Retries the busy operation as long as the next attempt starts before the deadline
+fn retry_until<T, E>(
+    deadline: Instant,
+    mut operation: impl FnMut() -> Result<T, E>,
+    is_busy: fn(&E) -> bool,
+) -> Result<T, E> {
+    let mut delays = backoff();
+    loop {
+        match operation() {
+            Err(why) if is_busy(&why) => {
+                let delay = delays.next().unwrap_or(MAX_DELAY);
+                if Instant::now() + delay >= deadline {
+                    return Err(why);
+                }
+                debug!("Resource busy, retrying in {:?}", delay);
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Advisory lock shared by every xcon process, taken on a file next to the guarded
+/// resource and released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Takes the exclusive lock, creating the lock file when missing and waiting
+    /// while another command holds it
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self, TodoError> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+        retry(
+            || file.try_lock(),
+            |why| matches!(why, TryLockError::WouldBlock),
+        )
+        .map_err(|why| match why {
+            TryLockError::WouldBlock => TodoError::with_message(
+                TodoErrorType::Locked,
+                format!("{} is held by another command", path.display()),
+            ),
+            TryLockError::Error(why) => TodoError::io(TodoErrorType::StorageFailure, why),
+        })?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[test]
+fn file_lock_test() {
+    let path = std::env::temp_dir().join(format!("xcon-lock-{}.lock", std::process::id()));
+    let held = FileLock::acquire(&path).unwrap();
+    let other = OpenOptions::new().write(true).open(&path).unwrap();
+    assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+    drop(held);
+    assert!(other.try_lock().is_ok());
+    drop(other);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn backoff_test() {
+    let delays: Vec<Duration> = backoff().take(10).collect();
+    assert_eq!(delays[..3], [FIRST_DELAY, FIRST_DELAY * 2, FIRST_DELAY * 4]);
+    assert_eq!(delays.last(), Some(&MAX_DELAY));
+}
+
+#[test]
+fn retry_test() {
+    let mut attempts = 0;
+    let result: Result<i32, bool> = retry(
+        || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(true)
+            } else {
+                Ok(attempts)
+            }
+        },
+        |busy| *busy,
+    );
+    assert_eq!(result, Ok(3));
+    let failed: Result<(), bool> = retry(|| Err(false), |busy| *busy);
+    assert_eq!(failed, Err(false));
+}
+
+#[test]
+fn retry_until_test() {
+    let started = Instant::now();
+    let mut attempts = 0;
+    let slow: Result<(), bool> = retry_until(
+        started + Duration::from_millis(300),
+        || {
+            attempts += 1;
+            thread::sleep(Duration::from_millis(100));
+            Err(true)
+        },
+        |busy| *busy,
+    );
+    assert_eq!(slow, Err(true));
+    assert!(attempts <= 3);
+    assert!(started.elapsed() < Duration::from_millis(500));
+}
@@ -1,39 +1,79 @@
+mod editor;
+mod output;
 mod handler;
 mod domain;
 mod config;
+mod config_file;
+mod dates;
 mod persistence;
+mod store;
+mod lock;
+mod paths;
+mod memory;
+mod json_file;
+mod markdown;
 mod service;
+mod template;
 
 use clap::{load_yaml, App, ArgMatches};
-use handler::{handle_init,handle_test,handle_add,handle_list,handle_remove,handle_config_argument};
+use handler::{handle_init,handle_test,handle_migrate,handle_add,handle_list,handle_next,handle_search,handle_edit,handle_done,handle_reopen,handle_remove,handle_move,handle_copy,handle_tags,handle_import,handle_config_argument,handle_set,handle_config,handle_db,expand_alias,report_error};
+use domain::TodoError;
 /// Command line todo application 
 /// Below actions can be performed using this application
 /// - Init
 /// - Test
+/// - Migrate
 /// - Add
 /// - List by id 
 /// - List all
+/// - Next most urgent
+/// - Search
+/// - Edit by id
+/// - Done / Reopen by id
+/// - Tags
 /// - Remove all
 /// - Remove by id
+/// - Import
 /// #Example
 /// ```
 /// xcon init
 /// ```
 /// The above command initialize the default database and application configuration
-/// 
+///
+/// Failures are printed on stderr and the process exits with the code of the
+/// error category, see [`domain::TodoErrorType`]
 fn main() {
     let yaml = load_yaml!("cli.yml");
-    let matches = App::from(yaml).get_matches();
-    application(&matches);
+    let app = App::from(yaml);
+    let args = expand_alias(&app, std::env::args_os().collect());
+    let matches = app.get_matches_from(args);
+    if let Err(why) = application(&matches) {
+        report_error(&matches, &why);
+        std::process::exit(why.exit_code());
+    }
 }
 
-fn application(matches: &ArgMatches) {
-    let settings = handle_config_argument(matches);
-    handle_init(matches, &settings);
-    handle_test(matches, &settings);
-    handle_add(matches, &settings);
-    handle_list(matches, &settings);
-    handle_remove(matches, &settings);
+fn application(matches: &ArgMatches) -> Result<(), TodoError> {
+    let settings = handle_config_argument(matches)?;
+    handle_set(matches, &settings)?;
+    handle_init(matches, &settings)?;
+    handle_test(matches, &settings)?;
+    handle_migrate(matches, &settings)?;
+    handle_config(matches, &settings)?;
+    handle_db(matches, &settings)?;
+    handle_add(matches, &settings)?;
+    handle_list(matches, &settings)?;
+    handle_next(matches, &settings)?;
+    handle_search(matches, &settings)?;
+    handle_edit(matches, &settings)?;
+    handle_done(matches, &settings)?;
+    handle_reopen(matches, &settings)?;
+    handle_remove(matches, &settings)?;
+    handle_move(matches, &settings)?;
+    handle_copy(matches, &settings)?;
+    handle_tags(matches, &settings)?;
+    handle_import(matches, &settings)?;
+    Ok(())
 }
 
 
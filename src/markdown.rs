@@ -0,0 +1,669 @@
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::dates::parse_due;
+use crate::domain::{
+    Filter, Search, SearchHit, Status, Tag, Todo, TodoError, TodoErrorType, Urgency, ID,
+};
+use crate::lock::FileLock;
+use crate::memory::MemoryStore;
+use crate::store::{Change, NoteStore};
+
+const NOTE_EXTENSION: &str = "md";
+const INDEX_FILE: &str = ".xcon-index.json";
+const HEALTH_FILE: &str = ".xcon-health";
+const LOCK_FILE: &str = ".xcon-lock";
+const FRONT_MATTER: &str = "---";
+const SLUG_LENGTH: usize = 40;
+
+/// Store keeping every todo as a Markdown file in one directory, with the fields
+/// in YAML front matter and the content as the body:
+///
+/// ```text
+/// ---
+/// id: 3
+/// title: Buy milk
+/// tags: [errands]
+/// status: open
+/// created_at: 2026-10-14T09:00:00Z
+/// ---
+///
+/// From the market on the way back
+/// ```
+///
+/// Files may be written or edited by hand. A note without front matter takes its
+/// title from the first `#` heading or the file name, unknown keys are kept when
+/// xcon rewrites the file and notes without an id, or with the id of another note,
+/// are given the next free id. Ids of removed notes are never reused.
+/// Parsed notes are cached in an index file next to them and only parsed again
+/// when their size or modification time changes. The index also keeps the ids it
+/// gave, so a note is only rewritten when its todo changes and never on a read.
+/// The directory is locked while the store is open
+pub struct MarkdownStore {
+    dir: PathBuf,
+    files: BTreeMap<i32, PathBuf>,
+    todos: MemoryStore,
+    index: Index,
+    _lock: FileLock,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    /// Highest id ever handed out
+    last_id: i32,
+    /// Parsed notes by file name
+    notes: BTreeMap<String, Cached>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    modified: Duration,
+    len: u64,
+    todo: Todo,
+}
+
+impl MarkdownStore {
+    /// Counts the notes of the directory without reading them, ids are neither
+    /// assigned nor written back
+    pub fn count(path: &str) -> Result<usize, TodoError> {
+        let entries = fs::read_dir(path).map_err(|why| match why.kind() {
+            io::ErrorKind::NotFound => TodoError::caused_by(TodoErrorType::InitNotAvailable, why),
+            _ => TodoError::io(TodoErrorType::StorageFailure, why),
+        })?;
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_note(&entry.path()))
+            .count())
+    }
+
+    /// Creates the directory unless it is already there
+    pub fn create(path: &str) -> Result<(), TodoError> {
+        fs::create_dir_all(path)
+            .map_err(|why| TodoError::io(TodoErrorType::UnableToInitialize, why))
+    }
+
+    pub fn open(path: &str) -> Result<Self, TodoError> {
+        let dir = PathBuf::from(path);
+        let entries = fs::read_dir(&dir).map_err(|why| match why.kind() {
+            io::ErrorKind::NotFound => TodoError::caused_by(TodoErrorType::InitNotAvailable, why),
+            _ => TodoError::io(TodoErrorType::StorageFailure, why),
+        })?;
+        let lock = FileLock::acquire(dir.join(LOCK_FILE))?;
+        let mut cached = read_index(&dir);
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_note(path))
+            .collect();
+        paths.sort();
+
+        let mut changed = false;
+        let mut notes = Vec::new();
+        for path in paths {
+            let name = file_name(&path);
+            let (modified, len) = match stamp(&path) {
+                Ok(stamp) => stamp,
+                Err(why) => {
+                    warn!("Skipping note {} : {}", path.display(), why);
+                    continue;
+                }
+            };
+            let previous = cached.notes.remove(&name);
+            let known = previous.as_ref().and_then(|note| note.todo.id);
+            let todo = match previous {
+                Some(note) if note.modified == modified && note.len == len => note.todo,
+                _ => match read_note(&path, modified) {
+                    Ok(todo) => {
+                        changed = true;
+                        todo
+                    }
+                    Err(why) => {
+                        warn!("Skipping note {} : {}", path.display(), why);
+                        continue;
+                    }
+                },
+            };
+            notes.push((path, name, (modified, len), known, todo));
+        }
+        changed |= !cached.notes.is_empty();
+
+        // Ids the index gave a file stay with it, then the ids written in the notes
+        // are taken in file name order and the remaining notes get the next free ids
+        let mut ids = vec![None; notes.len()];
+        let mut taken = BTreeSet::new();
+        for (slot, (.., known, _)) in notes.iter().enumerate() {
+            if let Some(id) = known {
+                if taken.insert(*id) {
+                    ids[slot] = Some(*id);
+                }
+            }
+        }
+        for (slot, (.., todo)) in notes.iter().enumerate() {
+            if let (None, Some(id)) = (ids[slot], todo.id) {
+                if taken.insert(id) {
+                    ids[slot] = Some(id);
+                }
+            }
+        }
+        let mut last_id = taken
+            .iter()
+            .next_back()
+            .copied()
+            .unwrap_or_default()
+            .max(cached.last_id);
+        let mut index = Index {
+            last_id,
+            notes: BTreeMap::new(),
+        };
+        let mut files = BTreeMap::new();
+        let mut todos = Vec::new();
+        for ((path, name, (modified, len), known, todo), id) in notes.into_iter().zip(ids) {
+            let id = id.unwrap_or_else(|| {
+                last_id += 1;
+                last_id
+            });
+            changed |= known != Some(id);
+            let todo = Todo {
+                id: Some(id),
+                ..todo
+            };
+            files.insert(id, path);
+            todos.push(todo.clone());
+            index.notes.insert(
+                name,
+                Cached {
+                    modified,
+                    len,
+                    todo,
+                },
+            );
+        }
+
+        let mut store = Self {
+            dir,
+            files,
+            todos: MemoryStore::from_todos(todos).with_last_id(last_id),
+            index,
+            _lock: lock,
+        };
+        if changed {
+            store.write_index();
+        }
+        Ok(store)
+    }
+
+    /// Writes the notes that changed since `before` and removes the ones that are gone
+    fn sync<T>(&mut self, before: MemoryStore, result: T) -> Result<T, TodoError> {
+        let before: BTreeMap<Option<i32>, &Todo> =
+            before.todos().iter().map(|todo| (todo.id, todo)).collect();
+        let changed: Vec<Todo> = self
+            .todos
+            .todos()
+            .iter()
+            .filter(|todo| before.get(&todo.id) != Some(todo))
+            .cloned()
+            .collect();
+        for todo in &changed {
+            self.write_note(todo)?;
+        }
+        let todos = self.todos.todos();
+        let removed: Vec<i32> = self
+            .files
+            .keys()
+            .filter(|id| !todos.iter().any(|todo| todo.id == Some(**id)))
+            .copied()
+            .collect();
+        for id in removed {
+            if let Some(path) = self.files.remove(&id) {
+                fs::remove_file(&path)
+                    .or_else(|why| match why.kind() {
+                        io::ErrorKind::NotFound => Ok(()),
+                        _ => Err(why),
+                    })
+                    .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+                self.index.notes.remove(&file_name(&path));
+            }
+        }
+        self.write_index();
+        Ok(result)
+    }
+
+    /// Rewrites the note of the todo keeping the front matter keys xcon does not know,
+    /// new notes are named after their id and title
+    fn write_note(&mut self, todo: &Todo) -> Result<(), TodoError> {
+        let id = todo.id.unwrap_or_default();
+        let path = match self.files.get(&id) {
+            Some(path) => path.clone(),
+            None => self.new_note_path(todo),
+        };
+        let front = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| front_matter(&text))
+            .unwrap_or_default();
+        let text = render_note(todo, front)?;
+        let temporary = path.with_extension("md.tmp");
+        fs::write(&temporary, text)
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+        if let Ok((modified, len)) = stamp(&path) {
+            self.index.notes.insert(
+                file_name(&path),
+                Cached {
+                    modified,
+                    len,
+                    todo: todo.clone(),
+                },
+            );
+        }
+        self.files.insert(id, path);
+        Ok(())
+    }
+
+    fn new_note_path(&self, todo: &Todo) -> PathBuf {
+        let name = format!("{}-{}", todo.id.unwrap_or_default(), slug(&todo.title));
+        let mut path = self.dir.join(&name).with_extension(NOTE_EXTENSION);
+        let mut copy = 1;
+        while path.exists() {
+            copy += 1;
+            path = self
+                .dir
+                .join(format!("{}-{}", name, copy))
+                .with_extension(NOTE_EXTENSION);
+        }
+        path
+    }
+
+    /// The index is only a cache, failing to write it is not an error
+    fn write_index(&mut self) {
+        self.index.last_id = self.todos.last_id();
+        let written = serde_json::to_string(&self.index)
+            .map_err(io::Error::other)
+            .and_then(|json| fs::write(self.dir.join(INDEX_FILE), json));
+        if let Err(why) = written {
+            warn!("Unable to write the note index : {}", why);
+        }
+    }
+}
+
+impl NoteStore for MarkdownStore {
+    fn save(&mut self, todo: Todo) -> Result<ID, TodoError> {
+        let before = self.todos.clone();
+        let id = self.todos.save(todo)?;
+        self.sync(before, id)
+    }
+
+    fn find(&mut self, id: ID) -> Result<Option<Todo>, TodoError> {
+        self.todos.find(id)
+    }
+
+    fn update(&mut self, todo: Todo) -> Result<bool, TodoError> {
+        let before = self.todos.clone();
+        let updated = self.todos.update(todo)?;
+        self.sync(before, updated)
+    }
+
+    fn set_status(&mut self, id: ID, status: Status) -> Result<bool, TodoError> {
+        let before = self.todos.clone();
+        let updated = self.todos.set_status(id, status)?;
+        self.sync(before, updated)
+    }
+
+    fn remove(&mut self, id: ID) -> Result<bool, TodoError> {
+        let before = self.todos.clone();
+        let removed = self.todos.remove(id)?;
+        self.sync(before, removed)
+    }
+
+    fn remove_all(&mut self) -> Result<bool, TodoError> {
+        let before = self.todos.clone();
+        let removed = self.todos.remove_all()?;
+        self.sync(before, removed)
+    }
+
+    fn read_all(
+        &mut self,
+        filter: &Filter,
+        urgency: &Urgency,
+        sink: &mut dyn FnMut(Todo) -> bool,
+    ) -> Result<usize, TodoError> {
+        self.todos.read_all(filter, urgency, sink)
+    }
+
+    fn tags(&mut self) -> Result<Vec<Tag>, TodoError> {
+        self.todos.tags()
+    }
+
+    fn merge_tags(&mut self, sources: Vec<String>, target: String) -> Result<bool, TodoError> {
+        let before = self.todos.clone();
+        let merged = self.todos.merge_tags(sources, target)?;
+        self.sync(before, merged)
+    }
+
+    fn search(&mut self, search: &Search) -> Result<Vec<SearchHit>, TodoError> {
+        self.todos.search(search)
+    }
+
+    /// Validates the whole batch in memory before any note is written or removed
+    fn apply(&mut self, changes: Vec<Change>) -> Result<usize, TodoError> {
+        let before = self.todos.clone();
+        let applied = self.todos.apply(changes)?;
+        self.sync(before, applied)
+    }
+
+    /// Writes a file in the directory and reads it back
+    fn check(&mut self) -> Result<(), TodoError> {
+        let path = self.dir.join(HEALTH_FILE);
+        let probe = "health str";
+        let read = fs::write(&path, probe).and_then(|_| fs::read_to_string(&path));
+        let _ = fs::remove_file(&path);
+        match read {
+            Ok(text) if text == probe => Ok(()),
+            Ok(_) => Err(TodoError::build(TodoErrorType::StorageCorrupt)),
+            Err(why) => Err(TodoError::io(TodoErrorType::StorageFailure, why)),
+        }
+    }
+}
+
+fn is_note(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().and_then(|extension| extension.to_str()) == Some(NOTE_EXTENSION)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Modification time and size, what the index compares to know a note changed
+fn stamp(path: &Path) -> io::Result<(Duration, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((modified, metadata.len()))
+}
+
+fn read_index(dir: &Path) -> Index {
+    fs::read_to_string(dir.join(INDEX_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn read_note(path: &Path, modified: Duration) -> io::Result<Todo> {
+    let text = fs::read_to_string(path)?;
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let modified = DateTime::<Utc>::from(UNIX_EPOCH + modified);
+    Ok(parse_note(&text, &stem, modified))
+}
+
+/// Splits the text into the YAML between the leading `---` lines and the body
+fn split_note(text: &str) -> (Option<&str>, &str) {
+    let text = text.trim_start_matches('\u{feff}');
+    let rest = match text.strip_prefix(FRONT_MATTER).and_then(|rest| {
+        rest.strip_prefix('\n')
+            .or_else(|| rest.strip_prefix("\r\n"))
+    }) {
+        Some(rest) => rest,
+        None => return (None, text),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER || line.trim_end() == "..." {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+fn front_matter(text: &str) -> Option<Mapping> {
+    let (front, _) = split_note(text);
+    match serde_yaml::from_str::<Mapping>(front?) {
+        Ok(front) => Some(front),
+        Err(why) => {
+            warn!("Ignoring front matter that is not valid YAML : {}", why);
+            None
+        }
+    }
+}
+
+/// Reads whatever fields the note has, values that do not parse are left empty
+/// and missing timestamps fall back to the modification time of the file
+fn parse_note(text: &str, stem: &str, modified: DateTime<Utc>) -> Todo {
+    let front = front_matter(text).unwrap_or_default();
+    let (_, body) = split_note(text);
+    let field = |key: &str| front.get(&Value::from(key)).and_then(scalar);
+    let date = |key: &str| field(key).and_then(|date| parse_date(&date));
+
+    let mut body = body.trim_start_matches(['\r', '\n']).trim_end();
+    let mut title = field("title");
+    if title.is_none() {
+        if let Some(heading) = body.lines().next().and_then(|line| line.strip_prefix("# ")) {
+            title = Some(heading.trim().to_owned());
+            body = body[body.find('\n').unwrap_or(body.len())..].trim_start();
+        }
+    }
+    let mut tags: Vec<String> = match front.get(&Value::from("tags")) {
+        Some(Value::Sequence(tags)) => tags.iter().filter_map(scalar).collect(),
+        Some(tags) => scalar(tags)
+            .map(|tags| tags.split(',').map(str::to_owned).collect())
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    tags = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    Todo {
+        id: front
+            .get(&Value::from("id"))
+            .and_then(Value::as_i64)
+            .and_then(|id| i32::try_from(id).ok())
+            .filter(|id| *id > 0),
+        title: title.unwrap_or_else(|| stem.to_owned()),
+        content: body.to_owned(),
+        user_name: field("user_name").or_else(|| field("user")),
+        tags,
+        status: field("status")
+            .and_then(|status| status.trim().to_lowercase().parse().ok())
+            .unwrap_or_default(),
+        completed_at: date("completed_at"),
+        due: date("due"),
+        priority: field("priority").and_then(|priority| priority.parse().ok()),
+        created_at: date("created_at").or(Some(modified)),
+        updated_at: date("updated_at").or(Some(modified)),
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// RFC 3339 as written by xcon, or a date typed by hand like `2026-10-20`
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date.trim())
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            date.trim()
+                .starts_with(|c: char| c.is_ascii_digit())
+                .then(|| parse_due(date, Local::now()).ok())
+                .flatten()
+        })
+}
+
+fn render_note(todo: &Todo, mut front: Mapping) -> Result<String, TodoError> {
+    let date = |date: Option<DateTime<Utc>>| {
+        date.map(|date| Value::from(date.to_rfc3339_opts(SecondsFormat::AutoSi, true)))
+    };
+    set(
+        &mut front,
+        "id",
+        todo.id.map(|id| Value::from(i64::from(id))),
+    );
+    set(&mut front, "title", Some(Value::from(todo.title.as_str())));
+    set(&mut front, "tags", Some(Value::from(todo.tags.clone())));
+    set(
+        &mut front,
+        "status",
+        Some(Value::from(todo.status.as_str())),
+    );
+    set(
+        &mut front,
+        "priority",
+        todo.priority.map(|priority| Value::from(priority.as_str())),
+    );
+    set(&mut front, "due", date(todo.due));
+    set(&mut front, "created_at", date(todo.created_at));
+    set(&mut front, "updated_at", date(todo.updated_at));
+    set(&mut front, "completed_at", date(todo.completed_at));
+    let yaml = serde_yaml::to_string(&front)
+        .map_err(|why| TodoError::caused_by(TodoErrorType::StorageFailure, why))?;
+    let yaml = yaml.trim_start_matches("---\n").trim_end();
+    Ok(if todo.content.is_empty() {
+        format!("{0}\n{1}\n{0}\n", FRONT_MATTER, yaml)
+    } else {
+        format!("{0}\n{1}\n{0}\n\n{2}\n", FRONT_MATTER, yaml, todo.content)
+    })
+}
+
+/// Sets the key in place so hand written front matter keeps its order
+fn set(front: &mut Mapping, key: &str, value: Option<Value>) {
+    let key = Value::from(key);
+    match (front.get_mut(&key), value) {
+        (Some(current), Some(value)) => *current = value,
+        (None, Some(value)) => {
+            front.insert(key, value);
+        }
+        (_, None) => {
+            front.remove(&key);
+        }
+    }
+}
+
+/// Lowercase words of the title joined by dashes, safe in a file name
+fn slug(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>()
+        .join("-");
+    let slug: String = slug.chars().take(SLUG_LENGTH).collect();
+    match slug.trim_end_matches('-') {
+        "" => "note".to_owned(),
+        slug => slug.to_owned(),
+    }
+}
+
+#[test]
+fn parse_note_test() {
+    let modified = Utc::now();
+    let note = "---\nid: 4\ntitle: Buy milk\ntags: Errands, home\nproject: kitchen\npriority: h\ndue: 2026-10-20\n---\n\nFrom the market\n";
+    let todo = parse_note(note, "4-buy-milk", modified);
+    assert_eq!(todo.id, Some(4));
+    assert_eq!(todo.title, "Buy milk");
+    assert_eq!(todo.tags, vec!["errands", "home"]);
+    assert_eq!(todo.content, "From the market");
+    assert_eq!(todo.priority, Some(crate::domain::Priority::High));
+    assert!(todo.due.is_some());
+    assert_eq!(todo.created_at, Some(modified));
+
+    let rendered = render_note(&todo, front_matter(note).unwrap()).unwrap();
+    assert!(rendered.contains("project: kitchen"));
+    assert_eq!(parse_note(&rendered, "", modified), todo);
+
+    let plain = parse_note("# Call mum\n\nAbout sunday", "call", modified);
+    assert_eq!((plain.id, plain.title.as_str()), (None, "Call mum"));
+    assert_eq!(plain.content, "About sunday");
+    assert_eq!(parse_note("just text", "idea", modified).title, "idea");
+}
+
+#[test]
+fn markdown_store_test() {
+    let dir = std::env::temp_dir().join(format!("xcon-notes-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.to_str().unwrap();
+    assert!(MarkdownStore::open(path)
+        .err()
+        .unwrap()
+        .is(TodoErrorType::InitNotAvailable));
+    MarkdownStore::create(path).unwrap();
+
+    let mut store = MarkdownStore::open(path).unwrap();
+    store
+        .save(Todo::new("Buy milk".to_owned(), "and eggs".to_owned()))
+        .unwrap();
+    store
+        .save(Todo::new("Write report".to_owned(), String::new()))
+        .unwrap();
+    assert!(dir.join("1-buy-milk.md").is_file());
+    assert!(store.remove(2).unwrap());
+    fs::write(dir.join("idea.md"), "# Plant a tree\n\nIn the garden").unwrap();
+    fs::copy(dir.join("1-buy-milk.md"), dir.join("0-copy.md")).unwrap();
+    drop(store);
+    let original = fs::read_to_string(dir.join("1-buy-milk.md")).unwrap();
+
+    let titles = || {
+        let mut titles = Vec::new();
+        MarkdownStore::open(path)
+            .unwrap()
+            .read_all(
+                &Filter {
+                    sort: crate::domain::Sort::Id,
+                    ..Filter::default()
+                },
+                &Urgency::default(),
+                &mut |todo| {
+                    titles.push((todo.id.unwrap(), todo.title));
+                    true
+                },
+            )
+            .unwrap();
+        titles
+    };
+    let expected = vec![
+        (1, "Buy milk".to_owned()),
+        (3, "Buy milk".to_owned()),
+        (4, "Plant a tree".to_owned()),
+    ];
+    assert_eq!(titles(), expected);
+    assert_eq!(titles(), expected);
+    assert_eq!(
+        fs::read_to_string(dir.join("1-buy-milk.md")).unwrap(),
+        original
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("idea.md")).unwrap(),
+        "# Plant a tree\n\nIn the garden"
+    );
+
+    let mut reopened = MarkdownStore::open(path).unwrap();
+    assert!(reopened.set_status(4, Status::Done).unwrap());
+    drop(reopened);
+    let idea = fs::read_to_string(dir.join("idea.md")).unwrap();
+    assert!(idea.starts_with("---\nid: 4\ntitle: Plant a tree\n"));
+    assert_eq!(titles(), expected);
+    let _ = fs::remove_dir_all(&dir);
+}
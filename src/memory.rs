@@ -0,0 +1,581 @@
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::domain::{
+    Filter, Search, SearchHit, Sort, Status, Tag, Todo, TodoError, TodoErrorType, Urgency, ID,
+};
+use crate::store::{missing_record, Change, NoteStore};
+
+/// Words around the first match shown in a search snippet
+const SNIPPET_WORDS: usize = 12;
+const SNIPPET_ELLIPSIS: &str = "...";
+/// Term frequency saturation and length normalization, the defaults of FTS5
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Store holding every todo in memory, filtering, ordering and search are done
+/// here the same way the SQLite store does them in SQL
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryStore {
+    todos: Vec<Todo>,
+    /// Highest id ever handed out, ids of removed todo are not reused
+    last_id: i32,
+}
+
+impl MemoryStore {
+    pub fn from_todos(mut todos: Vec<Todo>) -> Self {
+        todos.sort_by_key(|todo| todo.id);
+        Self { todos, last_id: 0 }
+    }
+
+    /// Keeps allocating ids after the given one even when higher ids were removed
+    pub fn with_last_id(self, last_id: i32) -> Self {
+        Self { last_id, ..self }
+    }
+
+    pub fn todos(&self) -> &[Todo] {
+        &self.todos
+    }
+
+    pub fn last_id(&self) -> i32 {
+        self.todos
+            .iter()
+            .filter_map(|todo| todo.id)
+            .fold(self.last_id, i32::max)
+    }
+
+    fn position(&self, id: ID) -> Option<usize> {
+        self.todos
+            .iter()
+            .position(|todo| todo.id.map(i64::from) == Some(id))
+    }
+}
+
+impl NoteStore for MemoryStore {
+    fn save(&mut self, todo: Todo) -> Result<ID, TodoError> {
+        let now = Utc::now();
+        self.last_id = self.last_id() + 1;
+        self.todos.push(Todo {
+            id: Some(self.last_id),
+            tags: clean_tags(todo.tags),
+            created_at: todo.created_at.or(Some(now)),
+            updated_at: todo.updated_at.or(Some(now)),
+            ..todo
+        });
+        Ok(ID::from(self.last_id))
+    }
+
+    fn find(&mut self, id: ID) -> Result<Option<Todo>, TodoError> {
+        Ok(self.position(id).map(|index| self.todos[index].clone()))
+    }
+
+    fn update(&mut self, todo: Todo) -> Result<bool, TodoError> {
+        let index = match todo.id.and_then(|id| self.position(i64::from(id))) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let stored = &mut self.todos[index];
+        stored.title = todo.title;
+        stored.content = todo.content;
+        stored.due = todo.due;
+        stored.priority = todo.priority;
+        stored.tags = clean_tags(todo.tags);
+        stored.updated_at = Some(Utc::now());
+        Ok(true)
+    }
+
+    fn set_status(&mut self, id: ID, status: Status) -> Result<bool, TodoError> {
+        let index = match self.position(id) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let now = Utc::now();
+        let stored = &mut self.todos[index];
+        stored.status = status;
+        stored.completed_at = match status {
+            Status::Done => Some(now),
+            Status::Open => None,
+        };
+        stored.updated_at = Some(now);
+        Ok(true)
+    }
+
+    fn remove(&mut self, id: ID) -> Result<bool, TodoError> {
+        Ok(match self.position(id) {
+            Some(index) => {
+                self.todos.remove(index);
+                true
+            }
+            None => false,
+        })
+    }
+
+    fn remove_all(&mut self) -> Result<bool, TodoError> {
+        let removed = !self.todos.is_empty();
+        self.todos.clear();
+        Ok(removed)
+    }
+
+    fn read_all(
+        &mut self,
+        filter: &Filter,
+        urgency: &Urgency,
+        sink: &mut dyn FnMut(Todo) -> bool,
+    ) -> Result<usize, TodoError> {
+        let now = Utc::now();
+        let mut matching: Vec<&Todo> = self
+            .todos
+            .iter()
+            .filter(|todo| matches_filter(todo, filter))
+            .collect();
+        matching.sort_by(|first, second| compare(first, second, filter, urgency, now));
+        let page = matching
+            .into_iter()
+            .skip(filter.offset.max(0) as usize)
+            .take(
+                filter
+                    .limit
+                    .map_or(usize::MAX, |limit| limit.max(0) as usize),
+            );
+        let mut count = 0;
+        for todo in page {
+            count += 1;
+            if !sink(todo.clone()) {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    fn tags(&mut self) -> Result<Vec<Tag>, TodoError> {
+        let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+        for tag in self.todos.iter().flat_map(|todo| &todo.tags) {
+            *counts.entry(tag).or_default() += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(name, count)| Tag {
+                name: name.to_owned(),
+                count,
+            })
+            .collect())
+    }
+
+    fn merge_tags(&mut self, sources: Vec<String>, target: String) -> Result<bool, TodoError> {
+        let sources: Vec<&String> = sources.iter().filter(|source| **source != target).collect();
+        let mut found = false;
+        for todo in &mut self.todos {
+            if todo.tags.iter().any(|tag| sources.contains(&tag)) {
+                found = true;
+                let tags = todo
+                    .tags
+                    .drain(..)
+                    .map(|tag| {
+                        if sources.contains(&&tag) {
+                            target.clone()
+                        } else {
+                            tag
+                        }
+                    })
+                    .collect();
+                todo.tags = clean_tags(tags);
+            }
+        }
+        Ok(found)
+    }
+
+    fn search(&mut self, search: &Search) -> Result<Vec<SearchHit>, TodoError> {
+        let query = Query::parse(&search.query)?;
+        let documents: Vec<_> = self
+            .todos
+            .iter()
+            .map(|todo| (words(&todo.title), words(&todo.content)))
+            .collect();
+        let ranks = bm25(&query, &documents);
+        let mut hits: Vec<SearchHit> = self
+            .todos
+            .iter()
+            .zip(documents.iter().zip(ranks))
+            .filter(|(_, ((title, content), _))| query.matches(title, content))
+            .map(|(todo, (_, rank))| SearchHit {
+                todo: todo.clone(),
+                title: highlight(&todo.title, &query, &search.markers),
+                snippet: snippet(&todo.content, &query, &search.markers),
+                rank,
+            })
+            .collect();
+        hits.sort_by(|first, second| {
+            first
+                .rank
+                .partial_cmp(&second.rank)
+                .unwrap_or(Ordering::Equal)
+        });
+        hits.truncate(search.limit.max(0) as usize);
+        Ok(hits)
+    }
+
+    fn check(&mut self) -> Result<(), TodoError> {
+        Ok(())
+    }
+
+    /// Applies the changes to a copy that replaces the store once all of them succeeded
+    fn apply(&mut self, changes: Vec<Change>) -> Result<usize, TodoError> {
+        let mut staged = self.clone();
+        let mut applied = 0;
+        for change in changes {
+            match change {
+                Change::Save(todo) => {
+                    staged.save(todo)?;
+                }
+                Change::Remove(id) => {
+                    if !staged.remove(id)? {
+                        return Err(missing_record(id));
+                    }
+                }
+            }
+            applied += 1;
+        }
+        *self = staged;
+        Ok(applied)
+    }
+}
+
+/// Sorted and without duplicates, the way tags are read back from SQLite
+fn clean_tags(mut tags: Vec<String>) -> Vec<String> {
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn matches_filter(todo: &Todo, filter: &Filter) -> bool {
+    let tagged = |tag: &String| todo.tags.contains(tag);
+    let tags = filter.tags.is_empty()
+        || if filter.any_tag {
+            filter.tags.iter().any(tagged)
+        } else {
+            filter.tags.iter().all(tagged)
+        };
+    let before = |limit: Option<DateTime<Utc>>, date: Option<DateTime<Utc>>, inclusive: bool| {
+        limit.is_none_or(|limit| {
+            date.is_some_and(|date| date < limit || (inclusive && date == limit))
+        })
+    };
+    let after = |limit: Option<DateTime<Utc>>, date: Option<DateTime<Utc>>| {
+        limit.is_none_or(|limit| date.is_some_and(|date| date >= limit))
+    };
+    tags && filter.status.is_none_or(|status| todo.status == status)
+        && before(filter.due_before, todo.due, false)
+        && after(filter.created_since, todo.created_at)
+        && before(filter.created_until, todo.created_at, true)
+}
+
+/// Value a todo is ordered by, absent values always come last
+#[derive(PartialEq, PartialOrd)]
+enum Key {
+    Number(f64),
+    Date(DateTime<Utc>),
+    Text(String),
+}
+
+/// Order of two todo in a listing sorted as the filter asks, like the SQL of the
+/// SQLite store: natural direction of the sort field flipped by reverse with ties
+/// broken on the id
+pub fn compare(
+    first: &Todo,
+    second: &Todo,
+    filter: &Filter,
+    urgency: &Urgency,
+    now: DateTime<Utc>,
+) -> Ordering {
+    let key = |todo: &Todo| match filter.sort {
+        Sort::Urgency => Some(Key::Number(todo.urgency(urgency, now))),
+        Sort::Created => todo.created_at.map(Key::Date),
+        Sort::Updated => todo.updated_at.map(Key::Date),
+        Sort::Due => todo.due.map(Key::Date),
+        Sort::Priority => todo
+            .priority
+            .map(|priority| Key::Number(priority.level() as f64)),
+        Sort::Title => Some(Key::Text(todo.title.to_lowercase())),
+        Sort::Id => todo.id.map(|id| Key::Number(f64::from(id))),
+    };
+    let descending = matches!(
+        filter.sort,
+        Sort::Urgency | Sort::Created | Sort::Updated | Sort::Priority
+    ) != filter.reverse;
+    let ordering = match (key(first), key(second)) {
+        (Some(first), Some(second)) => {
+            let ordering = first.partial_cmp(&second).unwrap_or(Ordering::Equal);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    let tie_break = first.id.cmp(&second.id);
+    ordering.then(if filter.reverse {
+        tie_break.reverse()
+    } else {
+        tie_break
+    })
+}
+
+/// Lowercase words of the text without surrounding punctuation
+fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(normalize)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+}
+
+#[derive(Debug, PartialEq)]
+enum Term {
+    Word(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+impl Term {
+    fn occurrences(&self, words: &[String]) -> usize {
+        match self {
+            Term::Word(term) => words.iter().filter(|word| *word == term).count(),
+            Term::Prefix(prefix) => words.iter().filter(|word| word.starts_with(prefix)).count(),
+            Term::Phrase(phrase) => words
+                .windows(phrase.len().max(1))
+                .filter(|window| window == phrase)
+                .count(),
+        }
+    }
+    fn matches_word(&self, word: &str) -> bool {
+        match self {
+            Term::Word(term) => term == word,
+            Term::Prefix(prefix) => word.starts_with(prefix.as_str()),
+            Term::Phrase(phrase) => phrase.iter().any(|term| term == word),
+        }
+    }
+}
+
+/// Search query in the FTS5 syntax subset of words, `"phrases"`, `prefix*`,
+/// `AND`, `OR` and `NOT`. Terms are joined with AND unless OR is given,
+/// AND binds tighter than OR
+#[derive(Debug, PartialEq)]
+struct Query {
+    /// Any of the groups matches when all of its terms match, negated terms must not
+    groups: Vec<Vec<(bool, Term)>>,
+}
+impl Query {
+    fn parse(query: &str) -> Result<Self, TodoError> {
+        let invalid = || TodoError::build(TodoErrorType::InvalidQuery);
+        let mut groups = vec![Vec::new()];
+        let mut negated = false;
+        let mut expects_term = true;
+        let mut rest = query.trim();
+        while !rest.is_empty() {
+            let (token, quoted) = if let Some(phrase) = rest.strip_prefix('"') {
+                let end = phrase.find('"').ok_or_else(invalid)?;
+                rest = &phrase[end + 1..];
+                (&phrase[..end], true)
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let token = &rest[..end];
+                rest = &rest[end..];
+                (token, false)
+            };
+            rest = rest.trim_start();
+            match token {
+                "AND" if !quoted && !expects_term => expects_term = true,
+                "OR" if !quoted && !expects_term && !negated => {
+                    groups.push(Vec::new());
+                    expects_term = true;
+                }
+                "NOT" if !quoted && !expects_term => {
+                    negated = true;
+                    expects_term = true;
+                }
+                "AND" | "OR" | "NOT" if !quoted => return Err(invalid()),
+                _ => {
+                    let term = if quoted {
+                        Term::Phrase(words(token))
+                    } else if let Some(prefix) = token.strip_suffix('*') {
+                        Term::Prefix(normalize(prefix))
+                    } else {
+                        Term::Word(normalize(token))
+                    };
+                    if let Some(group) = groups.last_mut() {
+                        group.push((negated, term));
+                    }
+                    negated = false;
+                    expects_term = false;
+                }
+            }
+        }
+        if expects_term {
+            return Err(invalid());
+        }
+        Ok(Self { groups })
+    }
+
+    fn matches(&self, title: &[String], content: &[String]) -> bool {
+        self.groups.iter().any(|group| {
+            group.iter().all(|(negated, term)| {
+                let found = term.occurrences(title) + term.occurrences(content) > 0;
+                found != *negated
+            })
+        })
+    }
+
+    fn positive_terms(&self) -> impl Iterator<Item = &Term> {
+        self.groups
+            .iter()
+            .flatten()
+            .filter(|(negated, _)| !negated)
+            .map(|(_, term)| term)
+    }
+
+    fn matches_word(&self, word: &str) -> bool {
+        let word = normalize(word);
+        !word.is_empty() && self.positive_terms().any(|term| term.matches_word(&word))
+    }
+}
+
+/// Scores the title and content words of every todo the way the bm25 function of
+/// FTS5 does, so ranks of all stores share one scale where lower is a better match
+fn bm25(query: &Query, documents: &[(Vec<String>, Vec<String>)]) -> Vec<f64> {
+    let length =
+        |(title, content): &(Vec<String>, Vec<String>)| (title.len() + content.len()) as f64;
+    let total = documents.len() as f64;
+    let average = documents.iter().map(length).sum::<f64>() / total;
+    let mut ranks = vec![0.0; documents.len()];
+    for term in query.positive_terms() {
+        let frequencies: Vec<f64> = documents
+            .iter()
+            .map(|(title, content)| (term.occurrences(title) + term.occurrences(content)) as f64)
+            .collect();
+        let found = frequencies
+            .iter()
+            .filter(|frequency| **frequency > 0.0)
+            .count() as f64;
+        let idf = ((total - found + 0.5) / (found + 0.5)).ln().max(1e-6);
+        for ((rank, frequency), document) in ranks.iter_mut().zip(frequencies).zip(documents) {
+            let norm = 1.0 - BM25_B + BM25_B * length(document) / average;
+            *rank -= idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * norm);
+        }
+    }
+    ranks
+}
+
+/// Wraps the words of the text matching the query with the markers
+fn highlight(text: &str, query: &Query, markers: &(String, String)) -> String {
+    text.split(' ')
+        .map(|word| mark(word, query, markers))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn mark(word: &str, query: &Query, markers: &(String, String)) -> String {
+    if query.matches_word(word) {
+        format!("{}{}{}", markers.0, word, markers.1)
+    } else {
+        word.to_owned()
+    }
+}
+
+/// Highlighted window of words starting at the first match in the text
+fn snippet(text: &str, query: &Query, markers: &(String, String)) -> String {
+    let all: Vec<&str> = text.split_whitespace().collect();
+    let first = all
+        .iter()
+        .position(|word| query.matches_word(word))
+        .unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_WORDS / 4);
+    let end = (start + SNIPPET_WORDS).min(all.len());
+    let mut snippet = all[start..end]
+        .iter()
+        .map(|word| mark(word, query, markers))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if start > 0 {
+        snippet.insert_str(0, SNIPPET_ELLIPSIS);
+    }
+    if end < all.len() {
+        snippet.push_str(SNIPPET_ELLIPSIS);
+    }
+    snippet
+}
+
+#[test]
+fn memory_store_test() {
+    let mut store = MemoryStore::default();
+    for (title, tags) in [
+        ("buy milk", vec!["home"]),
+        ("write report", vec!["work", "home"]),
+    ] {
+        let todo = Todo {
+            tags: tags.into_iter().map(str::to_owned).collect(),
+            ..Todo::new(title.to_owned(), "before friday".to_owned())
+        };
+        store.save(todo).unwrap();
+    }
+    assert!(store.set_status(2, Status::Done).unwrap());
+    assert!(!store.remove(7).unwrap());
+
+    let mut titles = Vec::new();
+    let filter = Filter {
+        tags: vec!["home".to_owned()],
+        sort: Sort::Title,
+        reverse: true,
+        ..Filter::default()
+    };
+    store
+        .read_all(&filter, &Urgency::default(), &mut |todo| {
+            titles.push(todo.title);
+            true
+        })
+        .unwrap();
+    assert_eq!(titles, vec!["write report", "buy milk"]);
+
+    assert!(store
+        .merge_tags(vec!["home".to_owned()], "house".to_owned())
+        .unwrap());
+    let tags: Vec<(String, i64)> = store
+        .tags()
+        .unwrap()
+        .into_iter()
+        .map(|tag| (tag.name, tag.count))
+        .collect();
+    assert_eq!(tags, vec![("house".to_owned(), 2), ("work".to_owned(), 1)]);
+}
+
+#[test]
+fn memory_search_test() {
+    let mut store = MemoryStore::default();
+    store
+        .save(Todo::new("Buy milk".to_owned(), "and the eggs".to_owned()))
+        .unwrap();
+    store
+        .save(Todo::new("Milk the cows".to_owned(), String::new()))
+        .unwrap();
+    let search = |query: &str| Search {
+        query: query.to_owned(),
+        limit: 10,
+        markers: ("[".to_owned(), "]".to_owned()),
+    };
+    let hits = store.search(&search("milk NOT cows")).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].title, "Buy [milk]");
+    assert_eq!(store.search(&search("egg* OR cows")).unwrap().len(), 2);
+    assert_eq!(
+        store.search(&search("\"the eggs\"")).unwrap()[0].snippet,
+        "and [the] [eggs]"
+    );
+    assert!(store.search(&search("milk AND")).is_err());
+    assert!(store.search(&search("\"milk")).is_err());
+}
@@ -0,0 +1,393 @@
+//! Rendering of todo in the formats selected with `--format`.
+//!
+//! `json`, `ndjson` and `yaml` share one schema, every key is always present and
+//! absent values are `null`:
+//!
+//! | key            | type                                      |
+//! |----------------|-------------------------------------------|
+//! | `id`           | integer                                   |
+//! | `title`        | string                                    |
+//! | `content`      | string                                    |
+//! | `user_name`    | string                                    |
+//! | `tags`         | array of lowercase strings, sorted        |
+//! | `status`       | `"open"` or `"done"`                      |
+//! | `completed_at` | RFC 3339 UTC timestamp or null            |
+//! | `due`          | RFC 3339 UTC timestamp or null            |
+//! | `priority`     | `"L"`, `"M"`, `"H"` or null               |
+//! | `created_at`   | RFC 3339 UTC timestamp or null            |
+//! | `updated_at`   | RFC 3339 UTC timestamp or null            |
+//! | `due_relative` | string like `due in 2d` or `3d overdue`, or null |
+//! | `urgency`      | number rounded to two decimals            |
+//!
+//! Listings of every database with `--all-dbs` add a `db` key naming the database
+//! of the todo, as first column in `csv` and `table`.
+//!
+//! `json` prints a single array, `ndjson` one object per line and `csv` a header
+//! row followed by one row per todo with tags joined by `,`.
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, Error, Write};
+use std::str::FromStr;
+use terminal_size::{terminal_size, Width};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::dates::relative;
+use crate::domain::{Priority, Todo, Urgency};
+use crate::template::Template;
+
+const DEFAULT_WIDTH: usize = 100;
+const MIN_TEXT_WIDTH: usize = 20;
+const COLUMN_GAP: &str = "  ";
+const ELLIPSIS: char = '…';
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+    Yaml,
+    Plain,
+}
+impl Format {
+    /// Aligned table for people at a terminal, one JSON object per line for pipes
+    pub fn default_for(terminal: bool) -> Self {
+        if terminal {
+            Format::Table
+        } else {
+            Format::Ndjson
+        }
+    }
+    /// Human readable formats, the ones that get told when nothing was found
+    pub fn is_human(self) -> bool {
+        matches!(self, Format::Table | Format::Plain)
+    }
+}
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.trim().to_lowercase().as_str() {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "yaml" => Ok(Format::Yaml),
+            "plain" => Ok(Format::Plain),
+            _ => Err(format!(
+                "Not a valid format '{}', use table, json, ndjson, csv, yaml or plain",
+                format
+            )),
+        }
+    }
+}
+
+/// Todo as printed, with the due date relative to now and the urgency score
+#[derive(Serialize)]
+struct TodoView<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    db: Option<&'a str>,
+    #[serde(flatten)]
+    todo: &'a Todo,
+    due_relative: Option<String>,
+    urgency: f64,
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    db: Option<&'a str>,
+    id: Option<i32>,
+    title: &'a str,
+    content: &'a str,
+    tags: String,
+    status: &'a str,
+    priority: Option<Priority>,
+    due: Option<DateTime<Utc>>,
+    due_relative: Option<String>,
+    urgency: f64,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+/// Column widths of the table, the title and content share what the terminal has left
+struct Layout {
+    title: usize,
+    content: usize,
+}
+impl Layout {
+    const DB: usize = 10;
+    const ID: usize = 5;
+    const STATUS: usize = 4;
+    const PRIORITY: usize = 1;
+    const DUE: usize = 11;
+    const TAGS: usize = 14;
+    const URGENCY: usize = 5;
+
+    fn new(width: usize, db_column: bool) -> Self {
+        let mut fixed =
+            Self::ID + Self::STATUS + Self::PRIORITY + Self::DUE + Self::TAGS + Self::URGENCY;
+        let mut gaps = 7;
+        if db_column {
+            fixed += Self::DB;
+            gaps += 1;
+        }
+        let text = width
+            .saturating_sub(fixed + COLUMN_GAP.len() * gaps)
+            .max(MIN_TEXT_WIDTH);
+        let title = text * 2 / 5;
+        Self {
+            title,
+            content: text - title,
+        }
+    }
+}
+
+/// Writes todo one at a time so a listing never has to be held in memory,
+/// [`TodoWriter::finish`] closes whatever the format opened
+pub struct TodoWriter<W: Write> {
+    out: W,
+    format: Format,
+    urgency: Urgency,
+    now: DateTime<Utc>,
+    layout: Layout,
+    template: Option<Template>,
+    date_format: Option<String>,
+    db_column: bool,
+    written: usize,
+}
+impl<W: Write> TodoWriter<W> {
+    pub fn new(out: W, format: Format, urgency: Urgency) -> Self {
+        Self {
+            out,
+            format,
+            urgency,
+            now: Utc::now(),
+            layout: Layout::new(terminal_width(), false),
+            template: None,
+            date_format: None,
+            db_column: false,
+            written: 0,
+        }
+    }
+
+    /// Shows due dates in tables and plain lists with the strftime format instead
+    /// of relative to now
+    pub fn with_date_format(self, date_format: Option<String>) -> Self {
+        Self {
+            date_format,
+            ..self
+        }
+    }
+
+    /// Names the database of every todo, written with [`TodoWriter::write_from`]
+    pub fn with_db_column(self) -> Self {
+        Self {
+            layout: Layout::new(terminal_width(), true),
+            db_column: true,
+            ..self
+        }
+    }
+
+    /// Renders every todo with the template instead of the format
+    pub fn with_template(out: W, template: Template, urgency: Urgency) -> Self {
+        Self {
+            template: Some(template),
+            ..Self::new(out, Format::Plain, urgency)
+        }
+    }
+
+    pub fn write(&mut self, todo: &Todo) -> io::Result<()> {
+        self.write_todo(None, todo)
+    }
+
+    /// Writes a todo read from the named database
+    pub fn write_from(&mut self, db: &str, todo: &Todo) -> io::Result<()> {
+        self.write_todo(Some(db), todo)
+    }
+
+    fn write_todo(&mut self, db: Option<&str>, todo: &Todo) -> io::Result<()> {
+        let first = self.written == 0;
+        self.written += 1;
+        if let Some(template) = &self.template {
+            return writeln!(
+                self.out,
+                "{}",
+                template.render(todo, &self.urgency, self.now)
+            );
+        }
+        let view = TodoView {
+            db,
+            todo,
+            due_relative: todo.due.map(|due| relative(due, self.now)),
+            urgency: (todo.urgency(&self.urgency, self.now) * 100.0).round() / 100.0,
+        };
+        let due = match (&self.date_format, todo.due) {
+            (Some(format), Some(due)) => Some(due.with_timezone(&Local).format(format).to_string()),
+            _ => view.due_relative.clone(),
+        };
+        match self.format {
+            Format::Json => {
+                let separator = if first { "[\n" } else { ",\n" };
+                write!(self.out, "{}{}", separator, serde_json::to_string(&view)?)
+            }
+            Format::Ndjson => writeln!(self.out, "{}", serde_json::to_string(&view)?),
+            Format::Yaml => {
+                let yaml = serde_yaml::to_string(&[view]).map_err(Error::other)?;
+                writeln!(self.out, "{}", yaml.trim_start_matches("---\n").trim_end())
+            }
+            Format::Csv => {
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(first)
+                    .from_writer(&mut self.out);
+                csv.serialize(CsvRow {
+                    db,
+                    id: todo.id,
+                    title: &todo.title,
+                    content: &todo.content,
+                    tags: todo.tags.join(","),
+                    status: todo.status.as_str(),
+                    priority: todo.priority,
+                    due: todo.due,
+                    due_relative: view.due_relative,
+                    urgency: view.urgency,
+                    created_at: todo.created_at,
+                    updated_at: todo.updated_at,
+                    completed_at: todo.completed_at,
+                })?;
+                csv.flush()
+            }
+            Format::Plain => {
+                if let Some(db) = db {
+                    write!(self.out, "{}:", db)?;
+                }
+                write!(self.out, "{} {}", todo.id.unwrap_or_default(), todo.title)?;
+                if !todo.tags.is_empty() {
+                    write!(self.out, " [{}]", todo.tags.join(","))?;
+                }
+                if let Some(due) = due {
+                    write!(self.out, " ({})", due)?;
+                }
+                writeln!(self.out)
+            }
+            Format::Table => {
+                if first {
+                    self.write_row(
+                        "DB",
+                        ["ID", "ST", "P", "DUE", "TAGS", "URG", "TITLE", "CONTENT"],
+                    )?;
+                }
+                self.write_row(
+                    db.unwrap_or(""),
+                    [
+                        &todo.id.unwrap_or_default().to_string(),
+                        todo.status.as_str(),
+                        todo.priority.map_or("", Priority::as_str),
+                        due.as_deref().unwrap_or(""),
+                        &todo.tags.join(","),
+                        &format!("{:.2}", view.urgency),
+                        &todo.title,
+                        &todo.content,
+                    ],
+                )
+            }
+        }
+    }
+
+    /// The database cell is left out unless the writer has a database column
+    fn write_row(&mut self, db: &str, cells: [&str; 8]) -> io::Result<()> {
+        let widths = [
+            Layout::ID,
+            Layout::STATUS,
+            Layout::PRIORITY,
+            Layout::DUE,
+            Layout::TAGS,
+            Layout::URGENCY,
+            self.layout.title,
+            self.layout.content,
+        ];
+        let mut row: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| fit(cell, *width))
+            .collect();
+        if self.db_column {
+            row.insert(0, fit(db, Layout::DB));
+        }
+        writeln!(self.out, "{}", row.join(COLUMN_GAP).trim_end())
+    }
+
+    /// Whether the output is meant to be read by people rather than scripts
+    pub fn is_human(&self) -> bool {
+        self.template.is_none() && self.format.is_human()
+    }
+
+    /// Closes the JSON array and flushes, returns the number of todo written
+    pub fn finish(&mut self) -> io::Result<usize> {
+        match self.format {
+            Format::Json if self.written == 0 => writeln!(self.out, "[]")?,
+            Format::Json => writeln!(self.out, "\n]")?,
+            Format::Yaml if self.written == 0 => writeln!(self.out, "[]")?,
+            _ => (),
+        }
+        self.out.flush()?;
+        Ok(self.written)
+    }
+}
+
+fn terminal_width() -> usize {
+    terminal_size()
+        .map(|(Width(width), _)| usize::from(width))
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Pads or truncates the text to exactly `width` terminal columns, line breaks
+/// become spaces and wide characters count twice
+fn fit(text: &str, width: usize) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    pad(&truncate(&text, width), width)
+}
+
+/// Cuts the text to at most `width` terminal columns, ending with an ellipsis when cut
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_owned();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width + 1 > width {
+            break;
+        }
+        used += char_width;
+        truncated.push(c);
+    }
+    if width > 0 {
+        truncated.push(ELLIPSIS);
+    }
+    truncated
+}
+
+/// Fills the text with spaces up to `width` terminal columns
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text.width());
+    let mut padded = text.to_owned();
+    padded.extend(std::iter::repeat_n(' ', padding));
+    padded
+}
+
+#[test]
+fn fit_test() {
+    assert_eq!(fit("milk", 6), "milk  ");
+    assert_eq!(fit("milk and eggs", 6), "milk …");
+    assert_eq!(fit("日本語のメモ", 7), "日本語…");
+    assert_eq!(fit("two\nlines", 9), "two lines");
+}
@@ -0,0 +1,281 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{CONFIG_FILE, LEGACY_CONFIG_FILE};
+use crate::config_file::ConfigFile;
+use crate::domain::{TodoError, TodoErrorType};
+
+/// Environment variable naming the config file, overridden by `--config`
+pub const CONFIG_ENV: &str = "XCON_CONFIG";
+/// Environment variable naming the directory the databases are kept in
+pub const DATA_DIR_ENV: &str = "XCON_DATA_DIR";
+const APP_DIR: &str = "xcon";
+
+/// Where the config file and the databases are kept. By default the config is
+/// `$XDG_CONFIG_HOME/xcon/config.toml` and the databases are in `$XDG_DATA_HOME/xcon/`,
+/// falling back to `~/.config` and `~/.local/share` and to the current directory
+/// when there is no home directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    pub config: PathBuf,
+    /// `app.conf` of earlier versions, read when there is no TOML config. A config
+    /// named `app.conf` is read as such and written to `config.toml` next to it
+    pub legacy_config: Option<PathBuf>,
+    pub data_dir: PathBuf,
+    /// The config was named with `--config` or `XCON_CONFIG`, nothing is migrated to it
+    pub custom_config: bool,
+}
+
+impl Paths {
+    /// Resolves the locations from the `--config` argument and the environment
+    pub fn resolve(config: Option<&str>) -> Self {
+        Self::from_env(config, |key| env::var_os(key))
+    }
+
+    fn from_env(config: Option<&str>, var: impl Fn(&str) -> Option<OsString>) -> Self {
+        let set = |key: &str| var(key).filter(|value| !value.is_empty());
+        let base_dir = |xdg: &str, home: &[&str]| {
+            set(xdg)
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| {
+                    set("HOME").map(|dir| {
+                        home.iter()
+                            .fold(PathBuf::from(dir), |dir, part| dir.join(part))
+                    })
+                })
+                .map(|dir| dir.join(APP_DIR))
+                .unwrap_or_default()
+        };
+        let custom = config
+            .map(PathBuf::from)
+            .or_else(|| set(CONFIG_ENV).map(PathBuf::from));
+        let (config, legacy_config) = match &custom {
+            Some(path) if path.file_name() == Some(OsStr::new(LEGACY_CONFIG_FILE)) => {
+                (path.with_file_name(CONFIG_FILE), Some(path.clone()))
+            }
+            Some(path) => (path.clone(), None),
+            None => {
+                let dir = base_dir("XDG_CONFIG_HOME", &[".config"]);
+                (dir.join(CONFIG_FILE), Some(dir.join(LEGACY_CONFIG_FILE)))
+            }
+        };
+        Self {
+            custom_config: custom.is_some(),
+            config,
+            legacy_config,
+            data_dir: set(DATA_DIR_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| base_dir("XDG_DATA_HOME", &[".local", "share"])),
+        }
+    }
+
+    /// Config file that is read, the legacy one while there is no TOML config
+    pub fn config_source(&self) -> &Path {
+        match &self.legacy_config {
+            Some(legacy_config) if !self.config.exists() && legacy_config.exists() => legacy_config,
+            _ => &self.config,
+        }
+    }
+
+    /// Location of a database file or directory
+    pub fn data_file(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    /// Moves the legacy config earlier versions kept in `dir` to its location, with
+    /// the database it names, and returns what was moved where. Only an `app.conf`
+    /// holding nothing but xcon keys is taken, and nothing is overwritten
+    pub fn migrate_from(&self, dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>, TodoError> {
+        let stray_config = dir.join(LEGACY_CONFIG_FILE);
+        let legacy_config = match &self.legacy_config {
+            Some(legacy_config) if !self.custom_config => legacy_config,
+            _ => {
+                return Err(TodoError::invalid_input(
+                    "Nothing is migrated to a config named with --config or XCON_CONFIG",
+                ))
+            }
+        };
+        if !stray_config.is_file() || same_file(&stray_config, legacy_config) {
+            return Err(TodoError::with_message(
+                TodoErrorType::RecordNotFound,
+                format!("There is no {} in {}", LEGACY_CONFIG_FILE, dir.display()),
+            ));
+        }
+        if let Some(existing) = [&self.config, legacy_config]
+            .iter()
+            .find(|config| config.exists())
+        {
+            return Err(TodoError::with_message(
+                TodoErrorType::Conflict,
+                format!("{} already exists", existing.display()),
+            ));
+        }
+        let text = fs::read_to_string(&stray_config)
+            .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+        let foreign = ConfigFile::foreign_legacy_keys(&text);
+        if !foreign.is_empty() {
+            return Err(TodoError::invalid_input(format!(
+                "{} is not an xcon config, it has the keys `{}`",
+                stray_config.display(),
+                foreign.join("`, `")
+            )));
+        }
+        let config = ConfigFile::from_legacy(&text)
+            .map_err(|why| why.context(format!("Invalid config {}", stray_config.display())))?;
+        let backend = config.database.backend;
+        let database = dir.join(backend.path(&config.database.name));
+
+        let mut moves = vec![(stray_config, legacy_config.clone())];
+        for file in backend.files(&database) {
+            let target = self.data_dir.join(file.file_name().unwrap_or_default());
+            if same_file(&file, &target) {
+                continue;
+            }
+            if target.exists() {
+                return Err(TodoError::with_message(
+                    TodoErrorType::Conflict,
+                    format!("{} already exists", target.display()),
+                ));
+            }
+            moves.push((file, target));
+        }
+        let io_error = |why| TodoError::io(TodoErrorType::StorageFailure, why);
+        if let Some(parent) = legacy_config.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::create_dir_all(&self.data_dir).map_err(io_error)?;
+        // The config goes last, an interrupted move is taken up again by the next one
+        moves.rotate_left(1);
+        for (from, to) in &moves {
+            move_file(from, to).map_err(io_error)?;
+        }
+        Ok(moves)
+    }
+}
+
+fn same_file(path: &Path, other: &Path) -> bool {
+    match (path.canonicalize(), other.canonicalize()) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => false,
+    }
+}
+
+/// Renames the file, copying it when the target is on another file system
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to).or_else(|_| {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    })
+}
+
+#[test]
+fn resolve_paths_test() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |key: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| OsString::from(value))
+        }
+    };
+    let xdg = Paths::from_env(
+        None,
+        env(&[
+            ("HOME", "/home/ana"),
+            ("XDG_CONFIG_HOME", "/etc/ana"),
+            ("XDG_DATA_HOME", "relative"),
+        ]),
+    );
+    assert_eq!(xdg.config, PathBuf::from("/etc/ana/xcon/config.toml"));
+    assert_eq!(
+        xdg.legacy_config,
+        Some(PathBuf::from("/etc/ana/xcon/app.conf"))
+    );
+    assert_eq!(xdg.data_dir, PathBuf::from("/home/ana/.local/share/xcon"));
+
+    let overridden = Paths::from_env(
+        Some("/tmp/work.conf"),
+        env(&[
+            ("HOME", "/home/ana"),
+            (CONFIG_ENV, "/x.conf"),
+            (DATA_DIR_ENV, "data"),
+        ]),
+    );
+    assert_eq!(overridden.config, PathBuf::from("/tmp/work.conf"));
+    assert!(overridden.custom_config && !xdg.custom_config);
+    assert_eq!(overridden.legacy_config, None);
+    assert_eq!(
+        overridden.data_file("todo.store"),
+        PathBuf::from("data/todo.store")
+    );
+
+    let homeless = Paths::from_env(None, env(&[]));
+    assert_eq!(homeless.config, PathBuf::from(CONFIG_FILE));
+
+    let legacy = Paths::from_env(Some("old/app.conf"), env(&[]));
+    assert_eq!(legacy.config, PathBuf::from("old/config.toml"));
+    assert_eq!(legacy.legacy_config, Some(PathBuf::from("old/app.conf")));
+    assert_eq!(
+        homeless.data_file("todo.store"),
+        PathBuf::from("todo.store")
+    );
+}
+
+#[test]
+fn migrate_from_test() {
+    let root = env::temp_dir().join(format!("xcon-paths-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let cwd = root.join("cwd");
+    fs::create_dir_all(&cwd).unwrap();
+    fs::write(cwd.join(LEGACY_CONFIG_FILE), "[server]\nport=8080\n").unwrap();
+    fs::write(cwd.join("work.store"), "").unwrap();
+    fs::write(cwd.join("work.store-wal"), "").unwrap();
+    fs::write(cwd.join("cache.store"), "").unwrap();
+    let paths = Paths {
+        config: root.join("config").join(CONFIG_FILE),
+        legacy_config: Some(root.join("config").join(LEGACY_CONFIG_FILE)),
+        data_dir: root.join("data"),
+        custom_config: false,
+    };
+    let legacy_config = paths.legacy_config.clone().unwrap();
+
+    let foreign = paths.migrate_from(&cwd).err().unwrap();
+    assert!(foreign.is(TodoErrorType::InvalidInput));
+    assert!(foreign.to_string().contains("`[server]`, `port`"));
+    assert!(cwd.join(LEGACY_CONFIG_FILE).exists() && !root.join("data").exists());
+
+    fs::write(cwd.join(LEGACY_CONFIG_FILE), "db=work \nurgency.due=3\n").unwrap();
+    let moved = paths.migrate_from(&cwd).unwrap();
+    assert_eq!(
+        moved,
+        vec![
+            (cwd.join("work.store"), paths.data_file("work.store")),
+            (
+                cwd.join("work.store-wal"),
+                paths.data_file("work.store-wal")
+            ),
+            (cwd.join(LEGACY_CONFIG_FILE), legacy_config.clone())
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(&legacy_config).unwrap(),
+        "db=work \nurgency.due=3\n"
+    );
+    assert!(cwd.join("cache.store").exists());
+
+    fs::write(cwd.join(LEGACY_CONFIG_FILE), "db=cache\n").unwrap();
+    let existing = paths.migrate_from(&cwd).err().unwrap();
+    assert!(existing.is(TodoErrorType::Conflict));
+    assert!(cwd.join("cache.store").exists());
+
+    fs::remove_file(cwd.join(LEGACY_CONFIG_FILE)).unwrap();
+    assert!(paths
+        .migrate_from(&cwd)
+        .err()
+        .unwrap()
+        .is(TodoErrorType::RecordNotFound));
+    let _ = fs::remove_dir_all(&root);
+}
@@ -1,16 +1,296 @@
 
 extern crate rusqlite;
 
-use rusqlite::NO_PARAMS;
-use rusqlite::{Connection, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use rusqlite::types::ToSql;
+use rusqlite::{params, NO_PARAMS};
+use rusqlite::{
+    Connection, Error, ErrorCode, OpenFlags, Result, Row, Transaction, TransactionBehavior,
+};
+use std::fmt;
+use std::fs;
+use std::time::Duration;
 
-use crate::domain::Todo;
+use crate::domain::{
+    Filter, Priority, SchemaVersion, Search, SearchHit, Sort, Status, Tag, Todo, TodoError,
+    TodoErrorType, Urgency, ID,
+};
+use crate::lock::retry;
+use crate::store::{missing_record, Change, NoteStore};
 
 static DEFAULT_USER: &str = "Root";
 
-pub fn init_db(db: &String) -> Result<Response> {
+const TODO_SELECT: &str = "SELECT t.id,t.title,t.content,u.name,
+        (SELECT group_concat(g.name) FROM todo_tag tt
+        INNER JOIN tag g ON g.id = tt.tag_id WHERE tt.todo_id = t.id),
+        t.status,t.completed_at,t.due,t.priority,t.created_at,t.updated_at
+        from todo t
+        INNER JOIN user u
+        ON u.id = t.user_id";
+
+/// Urgency score computed the same way as [`Todo::urgency`], bound to the weights
+/// of priority, due date, age and tags and the current time in that order
+const URGENCY_SQL: &str = "(? * CASE t.priority WHEN 3 THEN 1.0 WHEN 2 THEN 0.65 WHEN 1 THEN 0.3 ELSE 0.0 END
+        + ? * CASE WHEN t.due IS NULL THEN 0.0
+            ELSE min(1.0, max(0.2, (julianday(?) - julianday(t.due) + 14.0) * 0.8 / 21.0 + 0.2)) END
+        + ? * CASE WHEN t.created_at IS NULL THEN 0.0
+            ELSE min(1.0, max(0.0, (julianday(?) - julianday(t.created_at)) / 365.0)) END
+        + ? * CASE (SELECT count(*) FROM todo_tag tt WHERE tt.todo_id = t.id)
+            WHEN 0 THEN 0.0 WHEN 1 THEN 0.8 WHEN 2 THEN 0.9 ELSE 1.0 END)";
+
+/// Schema changes in the order they were introduced, the schema version of a
+/// database is the number of migrations applied to it and is kept in `PRAGMA user_version`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create user, todo and health tables",
+        apply: create_base_tables,
+    },
+    Migration {
+        description: "Create tag tables",
+        apply: create_tag_tables,
+    },
+    Migration {
+        description: "Add status, due date, priority and timestamps to todo",
+        apply: add_todo_columns,
+    },
+    Migration {
+        description: "Create full text search index on todo",
+        apply: create_search_index,
+    },
+];
+
+struct Migration {
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Sqlite(rusqlite::Error),
+    /// The database was migrated by a newer build than this one
+    TooNew {
+        version: i64,
+        supported: i64,
+    },
+}
+impl From<rusqlite::Error> for SchemaError {
+    fn from(error: rusqlite::Error) -> Self {
+        SchemaError::Sqlite(error)
+    }
+}
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Sqlite(why) => write!(f, "{}", why),
+            SchemaError::TooNew { version, supported } => write!(
+                f,
+                "Db store schema version {} is newer than the supported version {}, please upgrade xcon",
+                version, supported
+            ),
+        }
+    }
+}
+impl std::error::Error for SchemaError {}
+
+/// Time a statement waits on a lock held by another connection before failing as busy
+const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn init_db(db: &str) -> std::result::Result<(), SchemaError> {
     let conn = Connection::open(db)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    configure(&conn)?;
+    retry(|| apply_migrations(&conn), is_schema_busy)?;
+
+    if let Err(_why) = insert_user(DEFAULT_USER, &conn) {
+        //TODO : ignore unique constraint error
+        //println!("Init {}",e);
+    }
+    Ok(())
+}
+
+/// Opens an existing store and brings its schema up to date
+fn open_db(db: &str) -> std::result::Result<Connection, SchemaError> {
+    let conn = open_existing(db)?;
+    retry(|| apply_migrations(&conn), is_schema_busy)?;
+    Ok(conn)
+}
+
+fn open_existing(db: &str) -> Result<Connection> {
+    let conn = connect(db, OpenFlags::default())?;
+    configure(&conn)?;
+    Ok(conn)
+}
+
+/// Opens the store without creating it or changing its journal mode, waiting on the
+/// locks of concurrent commands
+fn connect(db: &str, mut flags: OpenFlags) -> Result<Connection> {
+    flags.remove(OpenFlags::SQLITE_OPEN_CREATE);
+    let conn = Connection::open_with_flags(db, flags)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+/// Switches the store to write ahead logging, so readers are not blocked by a
+/// writer. The journal mode is kept in the file, a store that can not change it
+/// stays usable in its current mode
+fn configure(conn: &Connection) -> Result<()> {
+    let mode = retry(
+        || {
+            conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| {
+                row.get::<_, String>(0)
+            })
+        },
+        is_busy,
+    );
+    match mode {
+        Ok(mode) if mode.eq_ignore_ascii_case("wal") => {}
+        Ok(mode) => debug!("Store kept in {} journal mode", mode),
+        Err(why) => debug!("Unable to enable write ahead logging : {}", why),
+    }
+    Ok(())
+}
+
+/// Starts a transaction holding the write lock from the beginning, a deferred one
+/// upgrading to a write fails at once when another connection wrote in between
+fn write_transaction(conn: &Connection) -> Result<Transaction<'_>> {
+    Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+}
+
+/// Another connection holds the lock for longer than the busy timeout
+fn is_busy(why: &Error) -> bool {
+    match why {
+        Error::SqliteFailure(failure, _) => matches!(
+            failure.code,
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked
+        ),
+        _ => false,
+    }
+}
+
+fn is_schema_busy(why: &SchemaError) -> bool {
+    match why {
+        SchemaError::Sqlite(why) => is_busy(why),
+        SchemaError::TooNew { .. } => false,
+    }
+}
+
+/// Leaves write ahead logging, which moves the log into the store and deletes it
+/// along with its shared memory file. Refused as busy while another connection has
+/// the store open, the next open switches it back
+fn leave_wal(conn: &Connection) -> Result<()> {
+    retry(
+        || conn.query_row("PRAGMA journal_mode = DELETE", NO_PARAMS, |_| Ok(())),
+        is_busy,
+    )
+}
+
+/// Counts the todo of the store, opened read only so it is neither migrated nor
+/// switched to write ahead logging
+pub fn count_todo(db: &str) -> std::result::Result<usize, TodoError> {
+    let conn = connect(db, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(storage_error)?;
+    conn.query_row("SELECT count(*) FROM todo", NO_PARAMS, |row| {
+        row.get::<_, i64>(0)
+    })
+    .map(|count| count as usize)
+    .map_err(storage_error)
+}
+
+/// Renames the store once its log is moved into it, holding an exclusive lock so no
+/// other command reads or writes it meanwhile
+pub fn rename_store(from: &str, to: &str) -> std::result::Result<(), TodoError> {
+    let conn = connect(from, OpenFlags::default()).map_err(storage_error)?;
+    leave_wal(&conn).map_err(storage_error)?;
+    retry(|| conn.execute_batch("BEGIN EXCLUSIVE"), is_busy).map_err(storage_error)?;
+    let renamed =
+        fs::rename(from, to).map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why));
+    conn.execute_batch("COMMIT").map_err(storage_error)?;
+    renamed
+}
+
+/// Moves the todo into the store at `target` under a new id, removing it from the
+/// store at `source` in the same transaction. SQLite commits a transaction over
+/// attached stores atomically only in rollback journal mode, so both leave write
+/// ahead logging for the move and go back to it afterwards
+pub fn move_todo(source: &str, target: &str, id: ID) -> std::result::Result<ID, TodoError> {
+    drop(open_db(source).map_err(schema_error)?);
+    let conn = open_db(target).map_err(schema_error)?;
+    conn.execute("ATTACH DATABASE ?1 AS source", &[source])
+        .map_err(storage_error)?;
+    let moved = leave_wal(&conn).and_then(|_| retry(|| move_record(id, source, &conn), is_busy));
+    let restored = configure(&conn);
+    let moved = moved.map_err(storage_error)?;
+    restored.map_err(storage_error)?;
+    moved.ok_or_else(|| TodoError::build(TodoErrorType::RecordNotFound))
+}
+
+fn move_record(id: ID, source: &str, conn: &Connection) -> Result<Option<ID>> {
+    let tx = write_transaction(conn)?;
+    // The transaction holds the write lock of both stores, the todo is read as
+    // committed through a connection of its own
+    let todo = match read_one(id, &connect(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?)? {
+        Some(todo) => todo,
+        None => return Ok(None),
+    };
+    let moved = insert_todo(Todo { id: None, ..todo }, &tx)?;
+    tx.execute("DELETE FROM source.todo_tag WHERE todo_id = ?1", &[&id])?;
+    tx.execute("DELETE FROM source.todo WHERE id = ?1", &[&id])?;
+    tx.execute(
+        "DELETE FROM source.tag WHERE id NOT IN (SELECT tag_id FROM source.todo_tag)",
+        NO_PARAMS,
+    )?;
+    tx.commit()?;
+    Ok(Some(moved))
+}
+
+/// Reports the schema version of the store and the migrations it is missing
+pub fn schema_status(db: &str) -> std::result::Result<SchemaVersion, SchemaError> {
+    let conn = open_existing(db)?;
+    pending_migrations(&conn)
+}
+
+/// Applies the missing migrations and reports the ones applied
+pub fn migrate(db: &str) -> std::result::Result<SchemaVersion, SchemaError> {
+    let conn = open_existing(db)?;
+    apply_migrations(&conn)
+}
+
+fn pending_migrations(conn: &Connection) -> std::result::Result<SchemaVersion, SchemaError> {
+    let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+    let supported = MIGRATIONS.len() as i64;
+    if version > supported {
+        return Err(SchemaError::TooNew { version, supported });
+    }
+    let pending = MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(version as usize)
+        .map(|(index, migration)| (index as i64 + 1, migration.description.to_owned()))
+        .collect();
+    Ok(SchemaVersion {
+        current: version,
+        latest: supported,
+        pending,
+    })
+}
 
+/// Runs every pending migration in a single transaction, a failing migration leaves
+/// the store untouched
+fn apply_migrations(conn: &Connection) -> std::result::Result<SchemaVersion, SchemaError> {
+    let status = pending_migrations(conn)?;
+    if status.pending.is_empty() {
+        return Ok(status);
+    }
+    let tx = write_transaction(conn)?;
+    for migration in MIGRATIONS.iter().skip(status.current as usize) {
+        (migration.apply)(&tx)?;
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", status.latest))?;
+    tx.commit()?;
+    Ok(status)
+}
+
+fn create_base_tables(conn: &Connection) -> Result<()> {
     conn.execute(
         "create table if not exists user (
              id integer primary key,
@@ -34,136 +314,926 @@ pub fn init_db(db: &String) -> Result<Response> {
          )",
         NO_PARAMS,
     )?;
+    Ok(())
+}
+
+fn create_tag_tables(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists tag (
+             id integer primary key,
+             name text not null unique
+         )",
+        NO_PARAMS,
+    )?;
+    conn.execute(
+        "create table if not exists todo_tag (
+             todo_id integer not null references todo(id),
+             tag_id integer not null references tag(id),
+             primary key (todo_id, tag_id)
+         )",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
 
-    Ok(match insert_user(DEFAULT_USER, &conn) {
-        Ok(_) => Response::Success,
-        Err(_) => {
-            //TODO : ignore unique constraint error
-            //println!("Init {}",e);
-            Response::Success
+/// Columns are only added when missing, stores created before versioning may have some
+fn add_todo_columns(conn: &Connection) -> Result<()> {
+    add_column(conn, "todo", "status", "text not null default 'open'")?;
+    add_column(conn, "todo", "completed_at", "text")?;
+    add_column(conn, "todo", "due", "text")?;
+    add_column(conn, "todo", "priority", "integer")?;
+    add_column(conn, "todo", "created_at", "text")?;
+    add_column(conn, "todo", "updated_at", "text")?;
+    Ok(())
+}
+
+/// Adds the column to an existing table unless it is already there
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))?;
+    for name in columns {
+        if name? == column {
+            return Ok(());
         }
-    })
+    }
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+        NO_PARAMS,
+    )?;
+    Ok(())
 }
 
-pub fn check(conn: &Connection) -> Result<Response> {
-    #[derive(Debug)]
-    struct Health {
-        name: String,
+/// External content FTS5 index over title and content, kept in sync by triggers.
+/// A SQLite built without FTS5 gets no index, so the store stays usable and only
+/// search reports the missing module
+fn create_search_index(conn: &Connection) -> Result<()> {
+    let created = conn.execute_batch(
+        "create virtual table if not exists todo_fts using fts5(
+             title, content, content='todo', content_rowid='id', tokenize='porter unicode61'
+         );",
+    );
+    match created {
+        Err(why) if is_missing_fts5(&why) => {
+            warn!("Full text search is not available : {}", why);
+            return Ok(());
+        }
+        created => created?,
     }
+    conn.execute_batch(
+        "create trigger if not exists todo_fts_insert after insert on todo begin
+             insert into todo_fts (rowid, title, content) values (new.id, new.title, new.content);
+         end;
+         create trigger if not exists todo_fts_delete after delete on todo begin
+             insert into todo_fts (todo_fts, rowid, title, content)
+             values ('delete', old.id, old.title, old.content);
+         end;
+         create trigger if not exists todo_fts_update after update of title, content on todo begin
+             insert into todo_fts (todo_fts, rowid, title, content)
+             values ('delete', old.id, old.title, old.content);
+             insert into todo_fts (rowid, title, content) values (new.id, new.title, new.content);
+         end;
+         insert into todo_fts (todo_fts) values ('rebuild');",
+    )
+}
+
+fn is_missing_fts5(why: &Error) -> bool {
+    matches!(why, Error::SqliteFailure(_, Some(message)) if message == "no such module: fts5")
+}
 
+fn has_search_index(conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT count(*) FROM sqlite_master where name = 'todo_fts'",
+        NO_PARAMS,
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// Writes a row and reads it back, the store is healthy when it round trips
+fn check(conn: &Connection) -> Result<()> {
     let name = String::from("health str");
     conn.execute("INSERT INTO health (name) values (?1)", &[&name])?;
-    let mut stmt = conn.prepare("SELECT * FROM health;")?;
+    let mut stmt = conn.prepare("SELECT name FROM health;")?;
 
-    let health = stmt.query_map(NO_PARAMS, |row| Ok(Health { name: row.get(0)? }))?;
+    let health = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(0))?;
+    let found = health.collect::<Result<Vec<String>>>()?.contains(&name);
     conn.execute("DELETE FROM health", NO_PARAMS)?;
-    Ok(Response::Success)
-}
-
-pub enum CrudAction {
-    Save(Todo),
-    Find(i64),
-    Remove(i64),
-    FindAll,
-    RemoveAll,
-    HealthCheck,
-}
-pub enum Response {
-    List(Vec<Todo>),
-    One(Option<Todo>),
-    Success,
-    Error(String),
-}
-
-pub fn db_action(action: CrudAction, db: String) -> Response {
-    if let Ok(conn) = Connection::open(db) {
-        match action {
-            CrudAction::Save(todo) => insert_todo(todo, &conn).unwrap(),
-            CrudAction::Find(id) => match read_one(id, &conn) {
-                Ok(resp) => resp,
-                Err(_) => Response::Error("Failure".to_string()),
-            },
-            CrudAction::FindAll => read_all(&conn).unwrap(),
-            CrudAction::Remove(id) => remove_record(id, &conn).unwrap(),
-            CrudAction::RemoveAll => remove_all_records(&conn).unwrap(),
-            CrudAction::HealthCheck => match check(&conn) {
-                Ok(resp) => resp,
-                Err(why) => {
-                    println!("{}", why);
-                    panic!("{}", why)
-                }
-            },
-        }
+    if found {
+        Ok(())
     } else {
-        println!("Db store is not found, Please setup application");
-        Response::Error("Db store is not found, Please setup application".to_string())
+        Err(Error::QueryReturnedNoRows)
+    }
+}
+
+/// SQLite store, one connection held for the life of the store, which is one
+/// command, so the statements prepared with `prepare_cached` are reused across a batch
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens an existing store and brings its schema up to date
+    pub fn open(db: &str) -> std::result::Result<Self, TodoError> {
+        let conn = open_db(db).map_err(schema_error)?;
+        Ok(Self { conn })
+    }
+
+    /// Runs a write again while a concurrent command keeps the store busy
+    fn write<T>(
+        &self,
+        mut write: impl FnMut(&Connection) -> Result<T>,
+    ) -> std::result::Result<T, TodoError> {
+        retry(|| write(&self.conn), is_busy).map_err(storage_error)
+    }
+}
+
+impl NoteStore for SqliteStore {
+    fn save(&mut self, todo: Todo) -> std::result::Result<ID, TodoError> {
+        self.write(|conn| {
+            let tx = write_transaction(conn)?;
+            let id = insert_todo(todo.clone(), &tx)?;
+            tx.commit()?;
+            Ok(id)
+        })
+    }
+    fn find(&mut self, id: ID) -> std::result::Result<Option<Todo>, TodoError> {
+        read_one(id, &self.conn).map_err(storage_error)
+    }
+    fn update(&mut self, todo: Todo) -> std::result::Result<bool, TodoError> {
+        self.write(|conn| update_todo(todo.clone(), conn))
+    }
+    fn set_status(&mut self, id: ID, status: Status) -> std::result::Result<bool, TodoError> {
+        self.write(|conn| update_status(id, status, conn))
+    }
+    fn remove(&mut self, id: ID) -> std::result::Result<bool, TodoError> {
+        self.write(|conn| {
+            let tx = write_transaction(conn)?;
+            let removed = remove_record(id, &tx)?;
+            tx.commit()?;
+            Ok(removed)
+        })
+    }
+    fn remove_all(&mut self) -> std::result::Result<bool, TodoError> {
+        self.write(remove_all_records)
+    }
+    fn read_all(
+        &mut self,
+        filter: &Filter,
+        urgency: &Urgency,
+        sink: &mut dyn FnMut(Todo) -> bool,
+    ) -> std::result::Result<usize, TodoError> {
+        read_all(filter, urgency, &self.conn, sink).map_err(storage_error)
+    }
+    fn tags(&mut self) -> std::result::Result<Vec<Tag>, TodoError> {
+        read_tags(&self.conn).map_err(storage_error)
+    }
+    fn merge_tags(
+        &mut self,
+        sources: Vec<String>,
+        target: String,
+    ) -> std::result::Result<bool, TodoError> {
+        self.write(|conn| merge_tags(sources.clone(), target.clone(), conn))
+    }
+    fn search(&mut self, search: &Search) -> std::result::Result<Vec<SearchHit>, TodoError> {
+        if !has_search_index(&self.conn).map_err(storage_error)? {
+            return Err(TodoError::with_message(
+                TodoErrorType::StorageFailure,
+                "Full text search needs a SQLite built with FTS5",
+            ));
+        }
+        search_todo(search, &self.conn).map_err(storage_error)
+    }
+    fn check(&mut self) -> std::result::Result<(), TodoError> {
+        self.write(check)
     }
+    fn apply(&mut self, changes: Vec<Change>) -> std::result::Result<usize, TodoError> {
+        self.write(|conn| apply_changes(changes.clone(), conn))?
+            .map_err(missing_record)
+    }
+}
+
+/// Classifies a failure to open or migrate a store, a store that can not be opened
+/// has not been initialized
+pub fn schema_error(why: SchemaError) -> TodoError {
+    match why {
+        SchemaError::TooNew { .. } => TodoError::caused_by(TodoErrorType::IncompatibleSchema, why),
+        SchemaError::Sqlite(why) => storage_error(why),
+    }
+}
+
+/// Classifies a rusqlite failure, FTS5 reports malformed search queries as plain SQL
+/// errors starting with `fts5: syntax error`
+fn storage_error(why: Error) -> TodoError {
+    let error_type = match &why {
+        Error::SqliteFailure(_, Some(message)) if message.starts_with("fts5: syntax error") => {
+            TodoErrorType::InvalidQuery
+        }
+        Error::SqliteFailure(failure, _) => match failure.code {
+            ErrorCode::CannotOpen => TodoErrorType::InitNotAvailable,
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => TodoErrorType::Locked,
+            ErrorCode::PermissionDenied | ErrorCode::ReadOnly => TodoErrorType::PermissionDenied,
+            ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => TodoErrorType::StorageCorrupt,
+            ErrorCode::ConstraintViolation => TodoErrorType::Conflict,
+            _ => TodoErrorType::StorageFailure,
+        },
+        Error::QueryReturnedNoRows => TodoErrorType::RecordNotFound,
+        _ => TodoErrorType::StorageFailure,
+    };
+    TodoError::caused_by(error_type, why)
 }
 
-fn insert_user(name: &str, conn: &Connection) -> Result<Response> {
+fn insert_user(name: &str, conn: &Connection) -> Result<()> {
     let last_id: String = conn.last_insert_rowid().to_string();
     conn.execute(
         "INSERT INTO user (id,name) values (?1,?2)",
         &[&last_id, &name.to_string()],
     )?;
-    Ok(Response::Success)
+    Ok(())
 }
 
-fn insert_todo(todo: Todo, conn: &Connection) -> Result<Response> {
-    conn.execute(
-        "INSERT INTO todo (title,content,user_id) values (?1,?2, (SELECT id FROM user where name = ?3));",
-        &[&todo.title.to_string(),&todo.content.to_string(), &DEFAULT_USER.to_string()],
-    )?;
-
-    Ok(Response::Success)
+/// Inserts the todo with its tags under a new id and returns the id, keeping its
+/// status and timestamps when it has them. Runs in the transaction of the caller
+fn insert_todo(todo: Todo, conn: &Connection) -> Result<ID> {
+    let now = Utc::now();
+    conn.prepare_cached(
+        "INSERT INTO todo (title,content,user_id,status,completed_at,due,priority,created_at,updated_at)
+        values (?1,?2, (SELECT id FROM user where name = ?3),?4,?5,?6,?7,?8,?9);",
+    )?
+    .execute(params![
+        todo.title,
+        todo.content,
+        DEFAULT_USER,
+        todo.status.as_str(),
+        todo.completed_at,
+        todo.due,
+        todo.priority.map(Priority::level),
+        todo.created_at.unwrap_or(now),
+        todo.updated_at.unwrap_or(now)
+    ])?;
+    let id = conn.last_insert_rowid();
+    save_tags(id, &todo.tags, conn)?;
+    Ok(id)
 }
-fn read_one(id: i64, conn: &Connection) -> Result<Response> {
-    let mut stmt = conn.prepare(
-        "SELECT t.id,t.title,t.content,u.name from todo t
-        INNER JOIN user u
-        ON u.id = t.user_id where t.id = :id and u.id = (SELECT id FROM user where name = :name)",
+fn update_todo(todo: Todo, conn: &Connection) -> Result<bool> {
+    let tx = write_transaction(conn)?;
+    let updated = tx.execute_named(
+        "UPDATE todo SET title = :title, content = :content, due = :due, priority = :priority,
+        updated_at = :updated_at where id = :id",
+        &[
+            (":title", &todo.title),
+            (":content", &todo.content),
+            (":due", &todo.due),
+            (":priority", &todo.priority.map(Priority::level)),
+            (":updated_at", &Utc::now()),
+            (":id", &todo.id),
+        ],
     )?;
+    if updated == 0 {
+        return Ok(false);
+    }
+    save_tags(i64::from(todo.id.unwrap_or_default()), &todo.tags, &tx)?;
+    prune_tags(&tx)?;
+    tx.commit()?;
+    Ok(true)
+}
+fn update_status(id: i64, status: Status, conn: &Connection) -> Result<bool> {
+    let completed_at = match status {
+        Status::Done => Some(Utc::now()),
+        Status::Open => None,
+    };
+    let updated = conn
+        .prepare_cached(
+            "UPDATE todo SET status = ?1, completed_at = ?2, updated_at = ?3 where id = ?4",
+        )?
+        .execute(params![status.as_str(), completed_at, Utc::now(), id])?;
+    Ok(updated > 0)
+}
+fn save_tags(todo_id: i64, tags: &[String], conn: &Connection) -> Result<()> {
+    conn.prepare_cached("DELETE FROM todo_tag where todo_id = ?1")?
+        .execute(&[&todo_id])?;
+    for tag in tags {
+        conn.prepare_cached("INSERT OR IGNORE INTO tag (name) values (?1)")?
+            .execute(&[tag])?;
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO todo_tag (todo_id,tag_id) values (?1, (SELECT id FROM tag where name = ?2))",
+        )?
+        .execute(params![todo_id, tag])?;
+    }
+    Ok(())
+}
+fn prune_tags(conn: &Connection) -> Result<()> {
+    conn.prepare_cached("DELETE FROM tag where id NOT IN (SELECT tag_id FROM todo_tag)")?
+        .execute(NO_PARAMS)?;
+    Ok(())
+}
+fn map_todo(row: &Row) -> Result<Todo> {
+    let tags: Option<String> = row.get(4)?;
+    let mut tags: Vec<String> = tags
+        .map(|tags| tags.split(',').map(str::to_owned).collect())
+        .unwrap_or_default();
+    tags.sort();
+    let status: String = row.get(5)?;
+    let completed_at: Option<DateTime<Utc>> = row.get(6)?;
+    let due: Option<DateTime<Utc>> = row.get(7)?;
+    let priority: Option<i64> = row.get(8)?;
+    Ok(Todo {
+        id: Option::Some(row.get(0)?),
+        title: row.get(1)?,
+        content: row.get(2)?,
+        user_name: Option::Some(row.get(3)?),
+        tags,
+        status: status.parse().unwrap_or_default(),
+        completed_at,
+        due,
+        priority: priority.and_then(Priority::from_level),
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+    })
+}
+fn read_one(id: i64, conn: &Connection) -> Result<Option<Todo>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "{} where t.id = :id and u.id = (SELECT id FROM user where name = :name)",
+        TODO_SELECT
+    ))?;
 
     let mut rows = stmt.query_named(&[(":id", &id), (":name", &DEFAULT_USER)])?;
     let mut result: Option<Todo> = None;
     while let Some(row) = rows.next()? {
-        result = Some(Todo {
-            id: Option::Some(row.get(0)?),
-            title: row.get(1)?,
-            content: row.get(2)?,
-            user_name: Option::Some(row.get(3)?),
-        })
+        result = Some(map_todo(row)?)
+    }
+    Ok(result)
+}
+
+/// Reads the todo matching the filter in the requested order and page, handing
+/// each one to the sink as it comes off the cursor so memory use stays bounded.
+/// Returns the number of todo handed over
+fn read_all(
+    filter: &Filter,
+    urgency: &Urgency,
+    conn: &Connection,
+    sink: &mut dyn FnMut(Todo) -> bool,
+) -> Result<usize> {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+    if !filter.tags.is_empty() {
+        let placeholders = vec!["?"; filter.tags.len()].join(",");
+        let mut condition = format!(
+            "t.id IN (SELECT tt.todo_id FROM todo_tag tt
+            INNER JOIN tag g ON g.id = tt.tag_id
+            where g.name IN ({}) GROUP BY tt.todo_id",
+            placeholders
+        );
+        for tag in &filter.tags {
+            params.push(Box::new(tag.clone()));
+        }
+        if !filter.any_tag {
+            condition.push_str(" HAVING count(DISTINCT g.id) = ?");
+            params.push(Box::new(filter.tags.len() as i64));
+        }
+        condition.push(')');
+        conditions.push(condition);
     }
-    Ok(Response::One(result))
+    if let Some(status) = filter.status {
+        conditions.push("t.status = ?".to_owned());
+        params.push(Box::new(status.as_str()));
+    }
+    if let Some(due_before) = filter.due_before {
+        conditions.push("t.due < ?".to_owned());
+        params.push(Box::new(due_before));
+    }
+    if let Some(created_since) = filter.created_since {
+        conditions.push("t.created_at >= ?".to_owned());
+        params.push(Box::new(created_since));
+    }
+    if let Some(created_until) = filter.created_until {
+        conditions.push("t.created_at <= ?".to_owned());
+        params.push(Box::new(created_until));
+    }
+    let mut sql = TODO_SELECT.to_owned();
+    if !conditions.is_empty() {
+        sql.push_str(" where ");
+        sql.push_str(&conditions.join(" and "));
+    }
+    // Natural direction of each sort field and whether it may be null, nulls always sort last
+    let (key, descending, nullable) = match filter.sort {
+        Sort::Urgency => {
+            let now = Utc::now();
+            params.push(Box::new(urgency.priority));
+            params.push(Box::new(urgency.due));
+            params.push(Box::new(now));
+            params.push(Box::new(urgency.age));
+            params.push(Box::new(now));
+            params.push(Box::new(urgency.tags));
+            (URGENCY_SQL, true, false)
+        }
+        Sort::Created => ("t.created_at", true, true),
+        Sort::Updated => ("t.updated_at", true, true),
+        Sort::Due => ("t.due", false, true),
+        Sort::Priority => ("t.priority", true, true),
+        Sort::Title => ("t.title COLLATE NOCASE", false, false),
+        Sort::Id => ("t.id", false, false),
+    };
+    let direction = if descending != filter.reverse {
+        "DESC"
+    } else {
+        "ASC"
+    };
+    let tie_break = if filter.reverse { "DESC" } else { "ASC" };
+    sql.push_str(" ORDER BY ");
+    if nullable {
+        sql.push_str(&format!("{} IS NULL, ", key));
+    }
+    sql.push_str(&format!("{} {}, t.id {}", key, direction, tie_break));
+    sql.push_str(" LIMIT ? OFFSET ?");
+    params.push(Box::new(filter.limit.unwrap_or(-1)));
+    params.push(Box::new(filter.offset));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params)?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        count += 1;
+        if !sink(map_todo(row)?) {
+            break;
+        }
+    }
+    Ok(count)
 }
 
-fn read_all(conn: &Connection) -> Result<Response> {
+/// Full text search ranked by bm25, matches in the title and the content snippet
+/// are wrapped with the search markers
+fn search_todo(search: &Search, conn: &Connection) -> Result<Vec<SearchHit>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.*, highlight(todo_fts, 0, :open, :close) AS highlighted,
+        snippet(todo_fts, 1, :open, :close, '...', 12) AS snippet, bm25(todo_fts) AS rank
+        from todo_fts
+        INNER JOIN ({}) t
+        ON t.id = todo_fts.rowid
+        where todo_fts MATCH :query ORDER BY bm25(todo_fts) LIMIT :limit",
+        TODO_SELECT
+    ))?;
+    let hits = stmt.query_map_named(
+        &[
+            (":open", &search.markers.0),
+            (":close", &search.markers.1),
+            (":query", &search.query),
+            (":limit", &search.limit),
+        ],
+        |row| {
+            Ok(SearchHit {
+                todo: map_todo(row)?,
+                title: row.get("highlighted")?,
+                snippet: row.get("snippet")?,
+                rank: row.get("rank")?,
+            })
+        },
+    )?;
+    hits.collect()
+}
+
+fn read_tags(conn: &Connection) -> Result<Vec<Tag>> {
     let mut stmt = conn.prepare(
-        "SELECT t.id,t.title,t.content,u.name from todo t
-        INNER JOIN user u
-        ON u.id = t.user_id;",
+        "SELECT g.name, count(tt.todo_id) from tag g
+        INNER JOIN todo_tag tt
+        ON tt.tag_id = g.id GROUP BY g.id ORDER BY g.name",
     )?;
-    let todos = stmt.query_map(NO_PARAMS, |row| {
-        Ok(Todo {
-            id: Option::Some(row.get(0)?),
-            title: row.get(1)?,
-            content: row.get(2)?,
-            user_name: Option::Some(row.get(3)?),
+    let tags = stmt.query_map(NO_PARAMS, |row| {
+        Ok(Tag {
+            name: row.get(0)?,
+            count: row.get(1)?,
         })
     })?;
-    let collected: rusqlite::Result<Vec<Todo>> = todos.collect();
-    let result = match collected {
-        Ok(list) => list,
-        Err(_) => Vec::<Todo>::new(),
+    tags.collect()
+}
+
+/// Moves every todo of the source tags onto the target tag and drops the sources,
+/// renaming is a merge with a single source
+fn merge_tags(sources: Vec<String>, target: String, conn: &Connection) -> Result<bool> {
+    let tx = write_transaction(conn)?;
+    tx.execute("INSERT OR IGNORE INTO tag (name) values (?1)", &[&target])?;
+    let mut found = false;
+    for source in sources.iter().filter(|source| **source != target) {
+        tx.execute(
+            "INSERT OR IGNORE INTO todo_tag (todo_id,tag_id)
+            SELECT todo_id, (SELECT id FROM tag where name = ?2) FROM todo_tag
+            where tag_id = (SELECT id FROM tag where name = ?1)",
+            &[source, &target],
+        )?;
+        tx.execute(
+            "DELETE FROM todo_tag where tag_id = (SELECT id FROM tag where name = ?1)",
+            &[source],
+        )?;
+        found |= tx.execute("DELETE FROM tag where name = ?1", &[source])? > 0;
+    }
+    if !found {
+        return Ok(false);
+    }
+    prune_tags(&tx)?;
+    tx.commit()?;
+    Ok(true)
+}
+
+fn remove_all_records(conn: &Connection) -> Result<bool> {
+    let tx = write_transaction(conn)?;
+    tx.execute("DELETE FROM todo_tag", NO_PARAMS)?;
+    let removed = tx.execute("DELETE FROM todo", NO_PARAMS)?;
+    prune_tags(&tx)?;
+    tx.commit()?;
+    Ok(removed > 0)
+}
+fn remove_record(id: i64, conn: &Connection) -> Result<bool> {
+    let removed = delete_record(id, conn)?;
+    prune_tags(conn)?;
+    Ok(removed)
+}
+/// Deletes the todo and its tag links, leaving unused tags for the caller to prune
+fn delete_record(id: i64, conn: &Connection) -> Result<bool> {
+    conn.prepare_cached("DELETE FROM todo_tag where todo_id =?")?
+        .execute(&[&id])?;
+    let removed = conn
+        .prepare_cached("DELETE FROM todo where id =?")?
+        .execute(&[&id])?;
+    Ok(removed > 0)
+}
+
+/// Applies every change in one transaction, a remove of a missing record rolls back
+/// the whole batch and is reported with its id
+fn apply_changes(
+    changes: Vec<Change>,
+    conn: &Connection,
+) -> Result<std::result::Result<usize, ID>> {
+    let tx = write_transaction(conn)?;
+    let mut applied = 0;
+    for change in changes {
+        match change {
+            Change::Save(todo) => {
+                insert_todo(todo, &tx)?;
+            }
+            Change::Remove(id) => {
+                if !delete_record(id, &tx)? {
+                    return Ok(Err(id));
+                }
+            }
+        }
+        applied += 1;
+    }
+    prune_tags(&tx)?;
+    tx.commit()?;
+    Ok(Ok(applied))
+}
+
+#[test]
+fn apply_migrations_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    let applied = apply_migrations(&conn).unwrap();
+    assert_eq!(applied.current, 0);
+    assert_eq!(applied.pending.len(), MIGRATIONS.len());
+
+    let status = apply_migrations(&conn).unwrap();
+    assert_eq!(status.current, MIGRATIONS.len() as i64);
+    assert!(status.pending.is_empty());
+
+    conn.execute_batch("PRAGMA user_version = 99").unwrap();
+    assert!(matches!(
+        apply_migrations(&conn),
+        Err(SchemaError::TooNew { version: 99, .. })
+    ));
+}
+
+#[test]
+fn read_all_tag_filter_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let tagged = |title: &str, tags: &[&str]| Todo {
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..Todo::new(title.to_owned(), String::new())
+    };
+    for todo in [
+        tagged("both", &["home", "urgent"]),
+        tagged("home", &["home"]),
+        tagged("work", &["work"]),
+        tagged("none", &[]),
+    ] {
+        insert_todo(todo, &conn).unwrap();
+    }
+    let titles = |tags: &[&str], any_tag: bool| {
+        let filter = Filter {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            any_tag,
+            sort: Sort::Id,
+            ..Filter::default()
+        };
+        let mut titles = Vec::new();
+        read_all(&filter, &Urgency::default(), &conn, &mut |todo| {
+            titles.push(todo.title);
+            true
+        })
+        .unwrap();
+        titles
+    };
+
+    assert_eq!(titles(&["home"], false), ["both", "home"]);
+    assert_eq!(titles(&["home", "urgent"], false), ["both"]);
+    assert_eq!(titles(&["home", "work"], false), Vec::<String>::new());
+    assert_eq!(titles(&["urgent", "work"], true), ["both", "work"]);
+    assert_eq!(titles(&["missing"], true), Vec::<String>::new());
+}
+
+#[test]
+fn merge_tags_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let tagged = |title: &str, tags: &[&str]| Todo {
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..Todo::new(title.to_owned(), String::new())
+    };
+    insert_todo(tagged("both", &["home", "house"]), &conn).unwrap();
+    insert_todo(tagged("house", &["house"]), &conn).unwrap();
+    insert_todo(tagged("shop", &["shop"]), &conn).unwrap();
+    let tags = || -> Vec<(String, i64)> {
+        read_tags(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.name, tag.count))
+            .collect()
+    };
+
+    assert!(merge_tags(vec!["shop".to_owned()], "store".to_owned(), &conn).unwrap());
+    assert_eq!(
+        tags(),
+        [
+            ("home".to_owned(), 1),
+            ("house".to_owned(), 2),
+            ("store".to_owned(), 1)
+        ]
+    );
+
+    let sources = vec!["house".to_owned(), "store".to_owned()];
+    assert!(merge_tags(sources, "home".to_owned(), &conn).unwrap());
+    assert_eq!(tags(), [("home".to_owned(), 3)]);
+    assert_eq!(read_one(1, &conn).unwrap().unwrap().tags, ["home"]);
+
+    assert!(!merge_tags(vec!["missing".to_owned()], "home".to_owned(), &conn).unwrap());
+    assert_eq!(tags(), [("home".to_owned(), 3)]);
+
+    assert!(remove_all_records(&conn).unwrap());
+    assert!(tags().is_empty());
+    assert!(!remove_all_records(&conn).unwrap());
+}
+
+#[test]
+fn update_status_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let id = insert_todo(Todo::new("Buy milk".to_owned(), String::new()), &conn).unwrap();
+    insert_todo(Todo::new("Call home".to_owned(), String::new()), &conn).unwrap();
+    let created = read_one(id, &conn).unwrap().unwrap();
+    assert_eq!(created.completed_at, None);
+
+    assert!(update_status(id, Status::Done, &conn).unwrap());
+    let done = read_one(id, &conn).unwrap().unwrap();
+    assert_eq!(done.status, Status::Done);
+    assert!(done.completed_at.is_some());
+    assert!(done.updated_at >= created.updated_at);
+
+    let titles = |status: Option<Status>| {
+        let filter = Filter {
+            status,
+            sort: Sort::Id,
+            ..Filter::default()
+        };
+        let mut titles = Vec::new();
+        read_all(&filter, &Urgency::default(), &conn, &mut |todo| {
+            titles.push(todo.title);
+            true
+        })
+        .unwrap();
+        titles
+    };
+    assert_eq!(titles(Some(Status::Open)), ["Call home"]);
+    assert_eq!(titles(Some(Status::Done)), ["Buy milk"]);
+    assert_eq!(titles(None), ["Buy milk", "Call home"]);
+
+    assert!(update_status(id, Status::Open, &conn).unwrap());
+    let reopened = read_one(id, &conn).unwrap().unwrap();
+    assert_eq!(reopened.status, Status::Open);
+    assert_eq!(reopened.completed_at, None);
+    assert!(!update_status(9, Status::Done, &conn).unwrap());
+}
+
+#[test]
+fn read_all_sort_and_page_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let now = Utc::now();
+    let todos = vec![
+        Todo::new("plain".to_owned(), String::new()),
+        Todo {
+            priority: Some(Priority::Low),
+            tags: vec!["home".to_owned()],
+            ..Todo::new("low".to_owned(), String::new())
+        },
+        Todo {
+            due: Some(now - chrono::Duration::days(3)),
+            ..Todo::new("overdue".to_owned(), String::new())
+        },
+        Todo {
+            priority: Some(Priority::High),
+            due: Some(now + chrono::Duration::days(30)),
+            ..Todo::new("high".to_owned(), String::new())
+        },
+    ];
+    for todo in todos {
+        insert_todo(todo, &conn).unwrap();
+    }
+    let urgency = Urgency::default();
+    let titles = |filter: Filter| {
+        let mut todos = Vec::new();
+        read_all(&filter, &urgency, &conn, &mut |todo| {
+            todos.push(todo);
+            true
+        })
+        .unwrap();
+        todos
+    };
+
+    let mut expected = titles(Filter {
+        sort: Sort::Id,
+        ..Filter::default()
+    });
+    expected.sort_by(|first, second| {
+        second
+            .urgency(&urgency, now)
+            .partial_cmp(&first.urgency(&urgency, now))
+            .unwrap()
+    });
+    assert_eq!(titles(Filter::default()), expected);
+
+    let page = titles(Filter {
+        sort: Sort::Title,
+        reverse: true,
+        limit: Some(2),
+        offset: 1,
+        ..Filter::default()
+    });
+    let page: Vec<&str> = page.iter().map(|todo| todo.title.as_str()).collect();
+    assert_eq!(page, vec!["overdue", "low"]);
+}
+
+#[test]
+fn search_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let mut store = SqliteStore { conn };
+    store
+        .save(Todo::new("Go running".to_owned(), "in the park".to_owned()))
+        .unwrap();
+    let bills = store
+        .save(Todo::new(
+            "Pay bills".to_owned(),
+            "rent and power".to_owned(),
+        ))
+        .unwrap();
+    let search = |store: &mut SqliteStore, query: &str| {
+        let search = Search {
+            query: query.to_owned(),
+            limit: 10,
+            markers: ("[".to_owned(), "]".to_owned()),
+        };
+        store.search(&search)
+    };
+
+    let hits = search(&mut store, "run").unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].todo.title, "Go running");
+    assert_eq!(hits[0].title, "Go [running]");
+    assert_eq!(hits[0].snippet, "in the park");
+    assert_eq!(search(&mut store, "rent OR park").unwrap().len(), 2);
+    assert!(search(&mut store, "rent AND")
+        .err()
+        .unwrap()
+        .is(TodoErrorType::InvalidQuery));
+
+    let renamed = Todo {
+        id: Some(bills as i32),
+        ..Todo::new("Pay taxes".to_owned(), "before april".to_owned())
+    };
+    store.update(renamed).unwrap();
+    assert!(search(&mut store, "rent").unwrap().is_empty());
+    assert_eq!(
+        search(&mut store, "taxes").unwrap()[0].snippet,
+        "before april"
+    );
+
+    store.remove(bills).unwrap();
+    assert!(search(&mut store, "taxes").unwrap().is_empty());
+    assert_eq!(search(&mut store, "park").unwrap().len(), 1);
+}
+
+#[test]
+fn storage_error_test() {
+    let failure = |message: &str| {
+        Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+            Some(message.to_owned()),
+        )
     };
-    Ok(Response::List(result))
+    let syntax = storage_error(failure("fts5: syntax error near \"AND\""));
+    assert!(syntax.is(TodoErrorType::InvalidQuery));
+    let missing = failure("no such module: fts5");
+    assert!(is_missing_fts5(&missing));
+    assert!(storage_error(missing).is(TodoErrorType::StorageFailure));
+    assert!(!is_missing_fts5(&failure("no such table: todo")));
 }
 
-fn remove_all_records(conn: &Connection) -> Result<Response> {
-    conn.execute("DELETE FROM todo", NO_PARAMS)?;
-    Ok(Response::Success)
+#[test]
+fn open_error_test() {
+    let missing = SqliteStore::open("missing/todo.store");
+    let why = missing.err().unwrap();
+    assert!(why.is(TodoErrorType::InitNotAvailable));
+    assert!(std::error::Error::source(&why).is_some());
+}
+
+#[test]
+fn apply_changes_test() {
+    let conn = Connection::open_in_memory().unwrap();
+    apply_migrations(&conn).unwrap();
+    insert_user(DEFAULT_USER, &conn).unwrap();
+    let mut store = SqliteStore { conn };
+    let done = Todo {
+        status: Status::Done,
+        tags: vec!["home".to_owned()],
+        ..Todo::new("imported".to_owned(), String::new())
+    };
+    let changes = vec![
+        Change::Save(done),
+        Change::Save(Todo::new("second".to_owned(), String::new())),
+    ];
+    assert_eq!(store.apply(changes).unwrap(), 2);
+    assert_eq!(store.find(1).unwrap().unwrap().status, Status::Done);
+
+    let why = store
+        .apply(vec![Change::Remove(1), Change::Remove(9)])
+        .err()
+        .unwrap();
+    assert!(why.is(TodoErrorType::RecordNotFound));
+    assert!(store.find(1).unwrap().is_some());
+    assert_eq!(store.tags().unwrap().len(), 1);
+
+    let removed = store.apply(vec![Change::Remove(1), Change::Remove(2)]);
+    assert_eq!(removed.unwrap(), 2);
+    assert!(store.tags().unwrap().is_empty());
 }
-fn remove_record(id: i64, conn: &Connection) -> Result<Response> {
-    conn.execute("DELETE FROM todo where id =?", &[&id])?;
-    Ok(Response::Success)
+
+#[test]
+fn move_todo_test() {
+    let dir = std::env::temp_dir().join(format!("xcon-move-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let (home, work) = (path("home.store"), path("work.store"));
+    init_db(&home).unwrap();
+    init_db(&work).unwrap();
+    let todo = Todo {
+        status: Status::Done,
+        tags: vec!["errand".to_owned()],
+        ..Todo::new("Buy milk".to_owned(), "and eggs".to_owned())
+    };
+    let id = SqliteStore::open(&home).unwrap().save(todo).unwrap();
+    let find = |db: &str, id: ID| SqliteStore::open(db).unwrap().find(id).unwrap();
+    let tag_count = |db: &str| SqliteStore::open(db).unwrap().tags().unwrap().len();
+
+    // A delete refused by the source takes the copy in the target back with it
+    let trigger = |sql: &str| Connection::open(&home).unwrap().execute_batch(sql).unwrap();
+    trigger("CREATE TRIGGER keep BEFORE DELETE ON todo BEGIN SELECT RAISE(ABORT, 'kept'); END;");
+    let kept = move_todo(&home, &work, id).err().unwrap();
+    assert!(kept.is(TodoErrorType::Conflict));
+    assert!(find(&work, 1).is_none() && find(&home, id).is_some());
+    assert_eq!(tag_count(&work), 0);
+    trigger("DROP TRIGGER keep");
+
+    let moved = move_todo(&home, &work, id).unwrap();
+    let copy = find(&work, moved).unwrap();
+    assert_eq!(
+        (copy.status, copy.tags),
+        (Status::Done, vec!["errand".to_owned()])
+    );
+    assert!(find(&home, id).is_none());
+    assert_eq!((tag_count(&home), tag_count(&work)), (0, 1));
+    let missing = move_todo(&home, &work, id).err().unwrap();
+    assert!(missing.is(TodoErrorType::RecordNotFound));
+
+    for db in &[&home, &work] {
+        let mode: String = Connection::open(db)
+            .unwrap()
+            .query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+    }
+    let _ = fs::remove_dir_all(&dir);
 }
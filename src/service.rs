@@ -1,6 +1,16 @@
-use crate::domain::{Action, Todo, TodoError, TodoErrorType, TodoResponse, ID};
-use crate::persistence::{db_action, CrudAction, Response};
+use crate::domain::{
+    Action, Filter, Search, SearchHit, Status, Todo, TodoError, TodoErrorType, TodoResponse,
+    Urgency, ID,
+};
+use crate::memory::compare;
+use crate::persistence::move_todo;
+use crate::store::{Backend, Change, NoteStore};
 use cfg_if::*;
+use chrono::{DateTime, Utc};
+use log::warn;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::thread;
 
 cfg_if! {
     if #[cfg(test)] {
@@ -11,47 +21,471 @@ cfg_if! {
 }
 
 pub fn action_router(configuration: &Settings, action: Action) -> Result<TodoResponse, TodoError> {
-    let db = configuration.get_db();
     if configuration.is_config_available() {
-        match action {
-            Action::Save(todo) => save(todo, db),
-            Action::Fetch => fetch(db),
-            Action::FetchById(id) => fetch_by_id(id, db),
-            Action::Delete => delete(db),
-            Action::DeleteById(id) => delete_by_id(id, db),
-        }
+        let mut store = configuration.get_backend().open(&configuration.get_db())?;
+        route(store.as_mut(), action, configuration.get_urgency())
     } else {
         Err(TodoError::build(TodoErrorType::InitNotAvailable))
     }
 }
-fn save(todo: Todo, db: String) -> Result<TodoResponse, TodoError> {
-    db_action(CrudAction::Save(todo), db);
-    //TODO : Handle error
-    Ok(TodoResponse::Done)
+
+/// Found records of several databases by database name, with the databases that
+/// could not be read while others could
+pub type Federated<T> = (Vec<(String, T)>, Vec<(String, TodoError)>);
+
+/// Lists the todo matching the filter in every database, merged in the order and
+/// page the filter asks for as if they were kept in a single database
+pub fn list_all_dbs(settings: &Settings, filter: &Filter) -> Result<Federated<Todo>, TodoError> {
+    let databases = all_dbs(settings)?;
+    let first_pages = Filter {
+        limit: filter.limit.map(|limit| limit + filter.offset),
+        offset: 0,
+        ..filter.clone()
+    };
+    let (todos, failed) = federated_router(&databases, |store, urgency| {
+        let mut todos = Vec::new();
+        store.read_all(&first_pages, urgency, &mut |todo| {
+            todos.push(todo);
+            true
+        })?;
+        Ok(todos)
+    })?;
+    let page = merge_pages(todos, filter, &settings.get_urgency(), Utc::now());
+    Ok((page, failed))
+}
+
+/// Searches every database and keeps the best ranked hits of all of them
+pub fn search_all_dbs(
+    settings: &Settings,
+    search: &Search,
+) -> Result<Federated<SearchHit>, TodoError> {
+    let databases = all_dbs(settings)?;
+    let (mut hits, failed) = federated_router(&databases, |store, _| store.search(search))?;
+    hits.sort_by(|(_, first), (_, second)| {
+        first
+            .rank
+            .partial_cmp(&second.rank)
+            .unwrap_or(Ordering::Equal)
+    });
+    hits.truncate(search.limit.max(0) as usize);
+    Ok((hits, failed))
+}
+
+fn all_dbs(settings: &Settings) -> Result<Vec<Settings>, TodoError> {
+    if !settings.is_config_available() {
+        return Err(TodoError::build(TodoErrorType::InitNotAvailable));
+    }
+    Ok(settings
+        .db_names()?
+        .into_iter()
+        .map(|name| settings.update(name))
+        .collect())
+}
+
+/// Sorts the first pages of every database together and cuts the page asked for
+fn merge_pages(
+    mut todos: Vec<(String, Todo)>,
+    filter: &Filter,
+    urgency: &Urgency,
+    now: DateTime<Utc>,
+) -> Vec<(String, Todo)> {
+    todos.sort_by(|(_, first), (_, second)| compare(first, second, filter, urgency, now));
+    todos
+        .into_iter()
+        .skip(filter.offset.max(0) as usize)
+        .take(
+            filter
+                .limit
+                .map_or(usize::MAX, |limit| limit.max(0) as usize),
+        )
+        .collect()
+}
+
+/// Runs the read on every database at once, each on its own thread, and gathers
+/// the records read in the order the databases are given. Fails with the error of
+/// the first database when none of them could be read
+fn federated_router<T: Send>(
+    databases: &[Settings],
+    read: impl Fn(&mut dyn NoteStore, &Urgency) -> Result<Vec<T>, TodoError> + Sync,
+) -> Result<Federated<T>, TodoError> {
+    let targets: Vec<_> = databases
+        .iter()
+        .map(|settings| {
+            let path = settings.get_db();
+            (
+                settings.get_db_name(),
+                settings.get_backend(),
+                path,
+                settings.get_urgency(),
+            )
+        })
+        .collect();
+    let read = &read;
+    let results: Vec<_> = thread::scope(|scope| {
+        let readers: Vec<_> = targets
+            .iter()
+            .map(|(_, backend, path, urgency)| {
+                scope.spawn(move || {
+                    backend
+                        .open(path)
+                        .and_then(|mut store| read(store.as_mut(), urgency))
+                        .map_err(sendable)
+                })
+            })
+            .collect();
+        readers
+            .into_iter()
+            .map(|reader| {
+                reader.join().unwrap_or_else(|_| {
+                    Err((TodoErrorType::StorageFailure, "Reader panicked".to_owned()))
+                })
+            })
+            .collect()
+    });
+    let mut found = Vec::new();
+    let mut failed = Vec::new();
+    for ((name, ..), result) in targets.into_iter().zip(results) {
+        match result {
+            Ok(records) => found.extend(records.into_iter().map(|record| (name.clone(), record))),
+            Err((error_type, message)) => {
+                failed.push((name, TodoError::with_message(error_type, message)))
+            }
+        }
+    }
+    if !failed.is_empty() && failed.len() == databases.len() {
+        return Err(failed.swap_remove(0).1);
+    }
+    Ok((found, failed))
+}
+
+/// The cause of an error can not leave the thread it was raised on, it is folded
+/// into the message instead
+fn sendable(why: TodoError) -> (TodoErrorType, String) {
+    let message = match why.source() {
+        Some(source) => format!("{} : {}", why, source),
+        None => why.to_string(),
+    };
+    (why.error_type(), message)
+}
+
+/// Copies the todo into the target database under a new id, removing it from the
+/// source when moved, in a single transaction between SQLite databases. Both
+/// databases must already exist
+pub fn transfer_router(
+    source: &Settings,
+    target: &Settings,
+    id: ID,
+    remove: bool,
+) -> Result<TodoResponse, TodoError> {
+    if !source.is_config_available() {
+        return Err(TodoError::build(TodoErrorType::InitNotAvailable));
+    }
+    if source.get_db() == target.get_db() {
+        return Err(TodoError::invalid_input(
+            "The todo is already in that database",
+        ));
+    }
+    if !target.get_backend().exists(&target.get_db()) {
+        return Err(TodoError::with_message(
+            TodoErrorType::RecordNotFound,
+            format!("Database {} not found", target.get_db_name()),
+        ));
+    }
+    if remove && source.get_backend() == Backend::Sqlite && target.get_backend() == Backend::Sqlite
+    {
+        return move_todo(&source.get_db(), &target.get_db(), id).map(TodoResponse::Transferred);
+    }
+    let mut from = source.get_backend().open(&source.get_db())?;
+    let mut to = target.get_backend().open(&target.get_db())?;
+    transfer(from.as_mut(), to.as_mut(), id, remove).map(TodoResponse::Transferred)
+}
+
+/// Saves a copy of the todo in the target and, when moved, removes the original.
+/// A copy whose original can not be removed is taken back, so the todo ends up in
+/// exactly one of the stores
+fn transfer(
+    source: &mut dyn NoteStore,
+    target: &mut dyn NoteStore,
+    id: ID,
+    remove: bool,
+) -> Result<ID, TodoError> {
+    let todo = source
+        .find(id)?
+        .ok_or_else(|| TodoError::build(TodoErrorType::RecordNotFound))?;
+    let copied = target.save(Todo { id: None, ..todo })?;
+    if remove {
+        if let Err(why) = source.apply(vec![Change::Remove(id)]) {
+            if let Err(undo) = target.apply(vec![Change::Remove(copied)]) {
+                warn!("Unable to remove the copy {} : {}", copied, undo);
+            }
+            return Err(why);
+        }
+    }
+    Ok(copied)
+}
+
+fn route(
+    store: &mut dyn NoteStore,
+    action: Action,
+    urgency: Urgency,
+) -> Result<TodoResponse, TodoError> {
+    match action {
+        Action::Save(todo) => store.save(todo).map(|_| TodoResponse::Done),
+        Action::Fetch(filter) => {
+            let mut todos = Vec::new();
+            store.read_all(&filter, &urgency, &mut |todo| {
+                todos.push(todo);
+                true
+            })?;
+            Ok(TodoResponse::All(todos))
+        }
+        Action::Stream(filter, mut sink) => store
+            .read_all(&filter, &urgency, &mut sink)
+            .map(TodoResponse::Streamed),
+        Action::FetchById(id) => store.find(id).map(TodoResponse::One),
+        Action::Update(todo) => written(store.update(todo)?),
+        Action::Complete(id) => written(store.set_status(id, Status::Done)?),
+        Action::Reopen(id) => written(store.set_status(id, Status::Open)?),
+        Action::Delete => store.remove_all().map(|_| TodoResponse::Done),
+        Action::DeleteById(id) => written(store.remove(id)?),
+        Action::FetchTags => store.tags().map(TodoResponse::Tags),
+        Action::MergeTags(sources, target) => written(store.merge_tags(sources, target)?),
+        Action::Search(search) => store.search(&search).map(TodoResponse::Hits),
+        Action::Batch(changes) => store.apply(changes).map(TodoResponse::Applied),
+    }
 }
-fn fetch(db: String) -> Result<TodoResponse, TodoError> {
-    Ok(match db_action(CrudAction::FindAll, db) {
-        Response::List(result) => TodoResponse::All(result),
-        _ => TodoResponse::Empty,
-    })
+
+/// Maps the outcome of a write, a write that touched nothing means the record is missing
+fn written(found: bool) -> Result<TodoResponse, TodoError> {
+    if found {
+        Ok(TodoResponse::Done)
+    } else {
+        Err(TodoError::build(TodoErrorType::RecordNotFound))
+    }
 }
-fn fetch_by_id(id: ID, db: String) -> Result<TodoResponse, TodoError> {
-    Ok(match db_action(CrudAction::Find(id), db) {
-        Response::One(result) => TodoResponse::One(result),
-        _ => TodoResponse::Empty,
-    })
+
+#[test]
+fn route_test() {
+    use crate::domain::{Filter, Todo};
+    use crate::memory::MemoryStore;
+
+    let mut store = MemoryStore::default();
+    let urgency = Urgency::default();
+    let todo = Todo::new("Buy milk".to_owned(), String::new());
+    route(&mut store, Action::Save(todo), urgency).unwrap();
+    assert!(matches!(
+        route(&mut store, Action::Complete(1), urgency),
+        Ok(TodoResponse::Done)
+    ));
+    let missing = route(&mut store, Action::DeleteById(9), urgency)
+        .err()
+        .unwrap();
+    assert!(missing.is(TodoErrorType::RecordNotFound));
+    match route(&mut store, Action::Fetch(Filter::default()), urgency) {
+        Ok(TodoResponse::All(todos)) => assert_eq!(todos[0].status, Status::Done),
+        _ => panic!("expected the saved todo"),
+    }
+    // emptying a store that is already empty is no error
+    for _ in 0..2 {
+        assert!(matches!(
+            route(&mut store, Action::Delete, urgency),
+            Ok(TodoResponse::Done)
+        ));
+    }
 }
 
-fn delete(db: String) -> Result<TodoResponse, TodoError> {
-    match db_action(CrudAction::RemoveAll, db) {
-        Response::Success => Ok(TodoResponse::Done),
-        _ => Err(TodoError::build(TodoErrorType::InitNotAvailable)),
+#[test]
+fn transfer_test() {
+    use crate::memory::MemoryStore;
+
+    let mut source = MemoryStore::default();
+    let mut target = MemoryStore::default();
+    target
+        .save(Todo::new("Already there".to_owned(), String::new()))
+        .unwrap();
+    let todo = Todo {
+        status: Status::Done,
+        tags: vec!["home".to_owned()],
+        ..Todo::new("Buy milk".to_owned(), "and eggs".to_owned())
+    };
+    let id = source.save(todo).unwrap();
+    let original = source.find(id).unwrap().unwrap();
+
+    let copied = transfer(&mut source, &mut target, id, false).unwrap();
+    assert_eq!(copied, 2);
+    let copy = target.find(copied).unwrap().unwrap();
+    assert_eq!(copy.id, Some(2));
+    assert_eq!((copy.status, &copy.tags), (Status::Done, &original.tags));
+    assert_eq!(copy.created_at, original.created_at);
+    assert!(source.find(id).unwrap().is_some());
+
+    let moved = transfer(&mut source, &mut target, id, true).unwrap();
+    assert_eq!(moved, 3);
+    assert!(source.find(id).unwrap().is_none());
+    let missing = transfer(&mut source, &mut target, id, true).err().unwrap();
+    assert!(missing.is(TodoErrorType::RecordNotFound));
+}
+
+#[test]
+fn merge_pages_test() {
+    use crate::domain::Sort;
+
+    let titled =
+        |db: &str, title: &str| (db.to_owned(), Todo::new(title.to_owned(), String::new()));
+    let filter = Filter {
+        sort: Sort::Title,
+        limit: Some(2),
+        offset: 1,
+        ..Filter::default()
+    };
+    let todos = vec![
+        titled("home", "b"),
+        titled("home", "d"),
+        titled("work", "a"),
+        titled("work", "c"),
+    ];
+    let page = merge_pages(todos, &filter, &Urgency::default(), Utc::now());
+    let page: Vec<_> = page
+        .iter()
+        .map(|(db, todo)| (db.as_str(), todo.title.as_str()))
+        .collect();
+    assert_eq!(page, [("home", "b"), ("work", "c")]);
+}
+
+#[test]
+fn federated_router_test() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("xcon-federated-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let database = |name: &str| {
+        let mut settings = Settings::default();
+        settings.expect_get_db_name().return_const(name.to_owned());
+        settings
+            .expect_get_db()
+            .return_const(path(&format!("{}.json", name)));
+        settings.expect_get_backend().return_const(Backend::Json);
+        settings
+            .expect_get_urgency()
+            .return_const(Urgency::default());
+        settings
+    };
+    for (name, titles) in &[
+        ("home", ["milk", "milk and more milk"]),
+        ("work", ["milk", "mail"]),
+    ] {
+        let file = path(&format!("{}.json", name));
+        Backend::Json.init(&file).unwrap();
+        let mut store = Backend::Json.open(&file).unwrap();
+        for title in titles {
+            store
+                .save(Todo::new((*title).to_owned(), String::new()))
+                .unwrap();
+        }
     }
+    fs::write(path("broken.json"), "not json").unwrap();
+    let search = Search {
+        query: "milk".to_owned(),
+        limit: 10,
+        markers: Default::default(),
+    };
+    let read = |store: &mut dyn NoteStore, _: &Urgency| store.search(&search);
+
+    let databases = [database("home"), database("broken"), database("work")];
+    let (hits, failed) = federated_router(&databases, read).unwrap();
+    let ranked: Vec<_> = hits
+        .iter()
+        .map(|(db, hit)| (db.as_str(), hit.title.as_str()))
+        .collect();
+    assert_eq!(
+        ranked,
+        [
+            ("home", "milk"),
+            ("home", "milk and more milk"),
+            ("work", "milk")
+        ]
+    );
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, "broken");
+    assert!(failed[0].1.is(TodoErrorType::StorageCorrupt));
+
+    let databases = [database("broken"), database("missing")];
+    let why = federated_router(&databases, read).err().unwrap();
+    assert!(why.is(TodoErrorType::StorageCorrupt));
+    let _ = fs::remove_dir_all(&dir);
 }
 
-fn delete_by_id(id: ID, db: String) -> Result<TodoResponse, TodoError> {
-    match db_action(CrudAction::Remove(id), db) {
-        Response::Success => Ok(TodoResponse::Done),
-        _ => Err(TodoError::build(TodoErrorType::InitNotAvailable)),
+#[test]
+fn search_all_dbs_test() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("xcon-search-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let files = dir.clone();
+    let database = move |name: &str| {
+        let (backend, extension) = if name == "large" {
+            (Backend::Sqlite, "store")
+        } else {
+            (Backend::Json, "json")
+        };
+        let file = files.join(format!("{}.{}", name, extension));
+        let file = file.to_string_lossy().into_owned();
+        let mut settings = Settings::default();
+        settings.expect_get_db_name().return_const(name.to_owned());
+        settings.expect_get_db().return_const(file);
+        settings.expect_get_backend().return_const(backend);
+        settings
+            .expect_get_urgency()
+            .return_const(Urgency::default());
+        settings
+    };
+    // the only todo of a small store is a weak hit, the rare word of a large one a strong hit
+    for (name, titles) in &[
+        ("small", &["milk"][..]),
+        ("large", &["milk", "bread", "eggs", "tea", "rice"][..]),
+        ("copy", &["milk", "bread", "eggs", "tea", "rice"][..]),
+    ] {
+        let settings = database(name);
+        let backend = settings.get_backend();
+        backend.init(&settings.get_db()).unwrap();
+        let mut store = backend.open(&settings.get_db()).unwrap();
+        for title in *titles {
+            store
+                .save(Todo::new((*title).to_owned(), String::new()))
+                .unwrap();
+        }
     }
+    let mut settings = Settings::default();
+    settings.expect_is_config_available().return_const(true);
+    settings.expect_db_names().returning(|| {
+        Ok(vec![
+            "small".to_owned(),
+            "large".to_owned(),
+            "copy".to_owned(),
+        ])
+    });
+    settings
+        .expect_update()
+        .returning(move |name| database(&name));
+    let mut search = Search {
+        query: "milk".to_owned(),
+        limit: 10,
+        markers: Default::default(),
+    };
+
+    let (hits, failed) = search_all_dbs(&settings, &search).unwrap();
+    assert!(failed.is_empty());
+    let databases: Vec<_> = hits.iter().map(|(db, _)| db.as_str()).collect();
+    assert_eq!(databases, ["large", "copy", "small"]);
+    // SQLite and the stores searched in memory rank the same todos alike
+    assert!((hits[0].1.rank - hits[1].1.rank).abs() < 1e-9);
+
+    search.limit = 2;
+    let (hits, _) = search_all_dbs(&settings, &search).unwrap();
+    let databases: Vec<_> = hits.iter().map(|(db, _)| db.as_str()).collect();
+    assert_eq!(databases, ["large", "copy"]);
+    let _ = fs::remove_dir_all(&dir);
 }
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::domain::{
+    Filter, SchemaVersion, Search, SearchHit, Status, Tag, Todo, TodoError, TodoErrorType,
+    Urgency, ID,
+};
+use crate::json_file::JsonStore;
+use crate::markdown::MarkdownStore;
+use crate::persistence::{
+    count_todo, init_db, migrate, rename_store, schema_error, schema_status, SqliteStore,
+};
+
+/// Storage of todo, the service talks to every backend through this trait.
+/// Writes return false when no record matched
+pub trait NoteStore {
+    /// Inserts the todo under a new id, keeping its status and timestamps, and
+    /// returns the id
+    fn save(&mut self, todo: Todo) -> Result<ID, TodoError>;
+    fn find(&mut self, id: ID) -> Result<Option<Todo>, TodoError>;
+    fn update(&mut self, todo: Todo) -> Result<bool, TodoError>;
+    fn set_status(&mut self, id: ID, status: Status) -> Result<bool, TodoError>;
+    fn remove(&mut self, id: ID) -> Result<bool, TodoError>;
+    fn remove_all(&mut self) -> Result<bool, TodoError>;
+    /// Hands the todo matching the filter to the sink in the requested order and page,
+    /// stops when the sink returns false. Returns the number of todo handed over
+    fn read_all(
+        &mut self,
+        filter: &Filter,
+        urgency: &Urgency,
+        sink: &mut dyn FnMut(Todo) -> bool,
+    ) -> Result<usize, TodoError>;
+    fn tags(&mut self) -> Result<Vec<Tag>, TodoError>;
+    /// Moves every todo of the source tags onto the target tag
+    fn merge_tags(&mut self, sources: Vec<String>, target: String) -> Result<bool, TodoError>;
+    fn search(&mut self, search: &Search) -> Result<Vec<SearchHit>, TodoError>;
+    /// Writes to the store and reads it back
+    fn check(&mut self) -> Result<(), TodoError>;
+    /// Applies all of the changes or, when one fails, none of them.
+    /// Returns the number of changes applied
+    fn apply(&mut self, changes: Vec<Change>) -> Result<usize, TodoError>;
+}
+
+/// One write of a batch given to [`NoteStore::apply`]
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// Inserts the todo under a new id, keeping its status and timestamps
+    Save(Todo),
+    Remove(ID),
+}
+
+/// Batch removing a record that does not exist
+pub fn missing_record(id: ID) -> TodoError {
+    TodoError::with_message(
+        TodoErrorType::RecordNotFound,
+        format!("Record id {} not found, nothing was changed", id),
+    )
+}
+
+/// Kind of store, configured with `backend` in the `[database]` table
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// SQLite database in `<db>.store`
+    #[default]
+    Sqlite,
+    /// Pretty printed JSON array in `<db>.json`, friendly to diff and version control
+    Json,
+    /// Directory named after the database with one Markdown file per todo
+    Markdown,
+}
+impl Backend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Json => "json",
+            Backend::Markdown => "markdown",
+        }
+    }
+    /// Location of the named database for this kind of store
+    pub fn path(self, db: &str) -> String {
+        match self {
+            Backend::Sqlite => format!("{}.store", db),
+            Backend::Json => format!("{}.json", db),
+            Backend::Markdown => db.to_owned(),
+        }
+    }
+
+    /// Suffixes of the files a database is kept in, appended to its path
+    fn suffixes(self) -> &'static [&'static str] {
+        match self {
+            Backend::Sqlite => &["", "-wal", "-shm"],
+            Backend::Json => &["", ".lock"],
+            Backend::Markdown => &[""],
+        }
+    }
+
+    /// Files and directories of the database at the path that exist
+    pub fn files(self, path: &Path) -> Vec<PathBuf> {
+        self.suffixes()
+            .iter()
+            .map(|suffix| {
+                let mut name = path.as_os_str().to_owned();
+                name.push(suffix);
+                PathBuf::from(name)
+            })
+            .filter(|file| file.exists())
+            .collect()
+    }
+
+    /// Names of the databases of this kind in the directory
+    pub fn databases(self, dir: &Path) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(why) => return Err(why),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+            if name.is_empty() || name.starts_with('.') {
+                continue;
+            }
+            let db = match self {
+                Backend::Markdown if path.is_dir() => Some(name),
+                Backend::Markdown => None,
+                Backend::Sqlite | Backend::Json => {
+                    let extension = self.path("");
+                    name.strip_suffix(extension.as_str())
+                        .filter(|db| !db.is_empty() && path.is_file())
+                }
+            };
+            names.extend(db.map(str::to_owned));
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Whether the database at the path exists
+    pub fn exists(self, path: &str) -> bool {
+        !self.files(Path::new(path)).is_empty()
+    }
+
+    /// Bytes taken by the database at the path on disk
+    pub fn size(self, path: &str) -> u64 {
+        self.files(Path::new(path))
+            .iter()
+            .map(|file| disk_size(file))
+            .sum()
+    }
+
+    /// Number of todo in the database at the path, read without migrating or
+    /// rewriting it
+    pub fn count(self, path: &str) -> Result<usize, TodoError> {
+        match self {
+            Backend::Sqlite => count_todo(path),
+            Backend::Json => JsonStore::count(path),
+            Backend::Markdown => MarkdownStore::count(path),
+        }
+    }
+
+    /// Moves the database with its companion files to another path, a SQLite one with
+    /// its write ahead log moved into it first
+    pub fn rename(self, from: &str, to: &str) -> Result<(), TodoError> {
+        if self == Backend::Sqlite {
+            return rename_store(from, to);
+        }
+        for suffix in self.suffixes() {
+            let source = format!("{}{}", from, suffix);
+            if Path::new(&source).exists() {
+                fs::rename(source, format!("{}{}", to, suffix))
+                    .map_err(|why| TodoError::io(TodoErrorType::StorageFailure, why))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the database with its companion files
+    pub fn remove(self, path: &str) -> io::Result<()> {
+        for file in self.files(Path::new(path)) {
+            if file.is_dir() {
+                fs::remove_dir_all(file)?;
+            } else {
+                fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the store when missing
+    pub fn init(self, path: &str) -> Result<(), TodoError> {
+        match self {
+            Backend::Sqlite => init_db(path).map(|_| ()).map_err(schema_error),
+            Backend::Json => JsonStore::create(path),
+            Backend::Markdown => MarkdownStore::create(path),
+        }
+    }
+
+    pub fn open(self, path: &str) -> Result<Box<dyn NoteStore>, TodoError> {
+        Ok(match self {
+            Backend::Sqlite => Box::new(SqliteStore::open(path)?),
+            Backend::Json => Box::new(JsonStore::open(path)?),
+            Backend::Markdown => Box::new(MarkdownStore::open(path)?),
+        })
+    }
+
+    /// Schema version of the store, only SQLite stores have migrations
+    pub fn schema_status(self, path: &str) -> Result<SchemaVersion, TodoError> {
+        match self {
+            Backend::Sqlite => schema_status(path).map_err(schema_error),
+            Backend::Json | Backend::Markdown => self.open(path).map(|_| SchemaVersion::default()),
+        }
+    }
+
+    /// Applies the missing migrations and reports the ones applied
+    pub fn migrate(self, path: &str) -> Result<SchemaVersion, TodoError> {
+        match self {
+            Backend::Sqlite => migrate(path).map_err(schema_error),
+            Backend::Json | Backend::Markdown => self.schema_status(path),
+        }
+    }
+}
+/// Size of the file, or of everything in the directory
+fn disk_size(path: &Path) -> u64 {
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| disk_size(&entry.path()))
+            .sum(),
+        Err(_) => fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default(),
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend.trim().to_lowercase().as_str() {
+            "sqlite" => Ok(Backend::Sqlite),
+            "json" => Ok(Backend::Json),
+            "markdown" => Ok(Backend::Markdown),
+            _ => Err(format!(
+                "Not a valid backend '{}', use sqlite, json or markdown",
+                backend
+            )),
+        }
+    }
+}
+
+#[test]
+fn databases_test() {
+    let dir = std::env::temp_dir().join(format!("xcon-databases-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("notes")).unwrap();
+    for file in &[
+        "work.store",
+        "work.store-wal",
+        "home.store",
+        "todo.json",
+        ".store",
+    ] {
+        fs::write(dir.join(file), "data").unwrap();
+    }
+    assert_eq!(
+        Backend::Sqlite.databases(&dir).unwrap(),
+        vec!["home".to_owned(), "work".to_owned()]
+    );
+    assert_eq!(
+        Backend::Json.databases(&dir).unwrap(),
+        vec!["todo".to_owned()]
+    );
+    assert_eq!(
+        Backend::Markdown.databases(&dir).unwrap(),
+        vec!["notes".to_owned()]
+    );
+
+    let work = dir.join("work.store").to_string_lossy().into_owned();
+    assert_eq!(Backend::Sqlite.size(&work), 8);
+    Backend::Sqlite.remove(&work).unwrap();
+    assert!(!Backend::Sqlite.exists(&work) && !dir.join("work.store-wal").exists());
+
+    let notes = dir.join("notes").to_string_lossy().into_owned();
+    let archive = dir.join("archive").to_string_lossy().into_owned();
+    Backend::Markdown.rename(&notes, &archive).unwrap();
+    assert!(!Backend::Markdown.exists(&notes) && Backend::Markdown.exists(&archive));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sqlite_rename_test() {
+    let dir = std::env::temp_dir().join(format!("xcon-rename-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    // A store left with a log that was never moved into it, as by a killed command
+    Backend::Sqlite.init(&path("work.store")).unwrap();
+    let mut store = Backend::Sqlite.open(&path("work.store")).unwrap();
+    store
+        .save(Todo::new("Buy milk".to_owned(), String::new()))
+        .unwrap();
+    for file in &["work.store", "work.store-wal"] {
+        fs::copy(path(file), path(&file.replace("work", "copy"))).unwrap();
+    }
+    drop(store);
+    Backend::Sqlite
+        .rename(&path("copy.store"), &path("job.store"))
+        .unwrap();
+    assert_eq!(
+        Backend::Sqlite.files(Path::new(&path("job.store"))),
+        vec![PathBuf::from(path("job.store"))]
+    );
+    assert!(!Backend::Sqlite.exists(&path("copy.store")));
+    assert_eq!(Backend::Sqlite.count(&path("job.store")).unwrap(), 1);
+
+    // Counting neither migrates the store nor switches it to write ahead logging
+    let conn = rusqlite::Connection::open(path("old.store")).unwrap();
+    conn.execute_batch("CREATE TABLE todo (id INTEGER PRIMARY KEY); INSERT INTO todo VALUES (1);")
+        .unwrap();
+    assert_eq!(Backend::Sqlite.count(&path("old.store")).unwrap(), 1);
+    let mode: String = conn
+        .query_row("PRAGMA journal_mode", rusqlite::NO_PARAMS, |row| row.get(0))
+        .unwrap();
+    assert_eq!(mode, "delete");
+    drop(conn);
+    let status = Backend::Sqlite.schema_status(&path("old.store")).unwrap();
+    assert_eq!(status.current, 0);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn backend_init_test() {
+    let dir = std::env::temp_dir().join(format!("xcon-backends-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for backend in &[Backend::Sqlite, Backend::Json, Backend::Markdown] {
+        assert_eq!(backend.as_str().parse::<Backend>().unwrap(), *backend);
+        let path = dir
+            .join(backend.path("todo"))
+            .to_string_lossy()
+            .into_owned();
+        assert!(!backend.exists(&path));
+        assert!(backend
+            .open(&path)
+            .err()
+            .unwrap()
+            .is(TodoErrorType::InitNotAvailable));
+
+        backend.init(&path).unwrap();
+        assert!(backend.exists(&path));
+        let status = backend.schema_status(&path).unwrap();
+        assert_eq!(status.current, status.latest);
+        assert!(backend.migrate(&path).unwrap().pending.is_empty());
+        let id = backend
+            .open(&path)
+            .unwrap()
+            .save(Todo::new("Buy milk".to_owned(), String::new()))
+            .unwrap();
+        let found = backend.open(&path).unwrap().find(id).unwrap();
+        assert_eq!(found.unwrap().title, "Buy milk");
+        assert_eq!(backend.count(&path).unwrap(), 1);
+    }
+    assert!("memory".parse::<Backend>().is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,291 @@
+use chrono::{DateTime, Local, Utc};
+use std::str::FromStr;
+
+use crate::dates::{check_date_format, relative};
+use crate::domain::{Priority, Todo, Urgency};
+use crate::output::{pad, truncate};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Output template like `{id}\t{title:truncate(30)} [{tags}] {due:relative}`.
+/// A placeholder names a todo field followed by `:` separated filters applied in
+/// order, `{{` and `}}` are literal braces and `\t`, `\n` are tab and new line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Field(Field, Vec<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Id,
+    Title,
+    Content,
+    Tags,
+    Status,
+    Priority,
+    Due,
+    Created,
+    Updated,
+    Completed,
+    Urgency,
+    User,
+}
+impl FromStr for Field {
+    type Err = String;
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        match field.trim() {
+            "id" => Ok(Field::Id),
+            "title" => Ok(Field::Title),
+            "content" => Ok(Field::Content),
+            "tags" => Ok(Field::Tags),
+            "status" => Ok(Field::Status),
+            "priority" => Ok(Field::Priority),
+            "due" => Ok(Field::Due),
+            "created" | "created_at" => Ok(Field::Created),
+            "updated" | "updated_at" => Ok(Field::Updated),
+            "completed" | "completed_at" => Ok(Field::Completed),
+            "urgency" => Ok(Field::Urgency),
+            "user" | "user_name" => Ok(Field::User),
+            _ => Err(format!(
+                "Not a valid template field '{}', use id, title, content, tags, status, priority, due, created, updated, completed, urgency or user",
+                field
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Upper,
+    Lower,
+    Truncate(usize),
+    Pad(usize),
+    Relative,
+    Date(String),
+    Default(String),
+}
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let filter = filter.trim();
+        let (name, argument) = match filter.find('(') {
+            Some(open) if filter.ends_with(')') => {
+                (&filter[..open], Some(&filter[open + 1..filter.len() - 1]))
+            }
+            _ => (filter, None),
+        };
+        let width = |argument: Option<&str>| {
+            argument
+                .and_then(|width| width.trim().parse::<usize>().ok())
+                .ok_or_else(|| {
+                    format!("Template filter '{}' needs a width like {}(20)", name, name)
+                })
+        };
+        match name {
+            "upper" => Ok(Filter::Upper),
+            "lower" => Ok(Filter::Lower),
+            "truncate" => Ok(Filter::Truncate(width(argument)?)),
+            "pad" => Ok(Filter::Pad(width(argument)?)),
+            "relative" => Ok(Filter::Relative),
+            "date" => {
+                let format = argument.unwrap_or(DEFAULT_DATE_FORMAT).to_owned();
+                check_date_format(&format)?;
+                Ok(Filter::Date(format))
+            }
+            "default" => Ok(Filter::Default(argument.unwrap_or_default().to_owned())),
+            _ => Err(format!(
+                "Not a valid template filter '{}', use upper, lower, truncate(n), pad(n), relative, date(format) or default(text)",
+                filter
+            )),
+        }
+    }
+}
+
+/// Value of a field while filters are applied, dates stay dates until a text filter needs them
+enum Value {
+    Text(String),
+    Date(Option<DateTime<Utc>>),
+}
+impl Value {
+    fn into_text(self) -> String {
+        match self {
+            Value::Text(text) => text,
+            Value::Date(date) => date.map(|date| date.to_rfc3339()).unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                }
+                ('\\', Some('t')) => {
+                    chars.next();
+                    text.push('\t');
+                }
+                ('\\', Some('n')) => {
+                    chars.next();
+                    text.push('\n');
+                }
+                ('\\', Some('\\')) => {
+                    chars.next();
+                    text.push('\\');
+                }
+                ('{', _) => {
+                    let mut placeholder = String::new();
+                    let mut depth = 0;
+                    loop {
+                        match chars.next() {
+                            Some('}') if depth == 0 => break,
+                            Some(c) => {
+                                match c {
+                                    '(' => depth += 1,
+                                    ')' => depth -= 1,
+                                    _ => (),
+                                }
+                                placeholder.push(c);
+                            }
+                            None => {
+                                return Err(format!("Unclosed '{{' in template '{}'", template))
+                            }
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(parse_placeholder(&placeholder)?);
+                }
+                ('}', _) => return Err(format!("Unopened '}}' in template '{}'", template)),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Template { segments })
+    }
+}
+
+/// Splits `field:filter(argument):filter` on the colons outside of filter arguments
+fn parse_placeholder(placeholder: &str) -> Result<Segment, String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0;
+    for c in placeholder.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ':' if depth == 0 => {
+                parts.push(std::mem::take(&mut part));
+                continue;
+            }
+            _ => (),
+        }
+        part.push(c);
+    }
+    parts.push(part);
+    let field = parts[0].parse()?;
+    let filters = parts[1..]
+        .iter()
+        .map(|filter| filter.parse())
+        .collect::<Result<Vec<Filter>, String>>()?;
+    Ok(Segment::Field(field, filters))
+}
+
+impl Template {
+    pub fn render(&self, todo: &Todo, urgency: &Urgency, now: DateTime<Utc>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Field(field, filters) => {
+                    let value = filters
+                        .iter()
+                        .fold(value(*field, todo, urgency, now), |value, filter| {
+                            apply(filter, value, now)
+                        });
+                    rendered.push_str(&value.into_text());
+                }
+            }
+        }
+        rendered
+    }
+}
+
+fn value(field: Field, todo: &Todo, urgency: &Urgency, now: DateTime<Utc>) -> Value {
+    match field {
+        Field::Id => Value::Text(todo.id.map(|id| id.to_string()).unwrap_or_default()),
+        Field::Title => Value::Text(todo.title.clone()),
+        Field::Content => Value::Text(todo.content.clone()),
+        Field::Tags => Value::Text(todo.tags.join(",")),
+        Field::Status => Value::Text(todo.status.as_str().to_owned()),
+        Field::Priority => Value::Text(todo.priority.map_or("", Priority::as_str).to_owned()),
+        Field::Due => Value::Date(todo.due),
+        Field::Created => Value::Date(todo.created_at),
+        Field::Updated => Value::Date(todo.updated_at),
+        Field::Completed => Value::Date(todo.completed_at),
+        Field::Urgency => Value::Text(format!("{:.2}", todo.urgency(urgency, now))),
+        Field::User => Value::Text(todo.user_name.clone().unwrap_or_default()),
+    }
+}
+
+fn apply(filter: &Filter, value: Value, now: DateTime<Utc>) -> Value {
+    match (filter, value) {
+        (Filter::Relative, Value::Date(date)) => {
+            Value::Text(date.map(|date| relative(date, now)).unwrap_or_default())
+        }
+        (Filter::Date(format), Value::Date(date)) => Value::Text(
+            date.map(|date| date.with_timezone(&Local).format(format).to_string())
+                .unwrap_or_default(),
+        ),
+        (Filter::Relative, value) | (Filter::Date(_), value) => value,
+        (Filter::Default(default), value) => match value.into_text() {
+            text if text.is_empty() => Value::Text(default.clone()),
+            text => Value::Text(text),
+        },
+        (Filter::Upper, value) => Value::Text(value.into_text().to_uppercase()),
+        (Filter::Lower, value) => Value::Text(value.into_text().to_lowercase()),
+        (Filter::Truncate(width), value) => Value::Text(truncate(&value.into_text(), *width)),
+        (Filter::Pad(width), value) => Value::Text(pad(&value.into_text(), *width)),
+    }
+}
+
+#[test]
+fn render_test() {
+    let now = Utc::now();
+    let todo = Todo {
+        id: Some(7),
+        tags: vec!["home".to_owned(), "work".to_owned()],
+        due: Some(now + chrono::Duration::hours(50)),
+        ..Todo::new("Buy groceries".to_owned(), String::new())
+    };
+    let render = |template: &str| {
+        template
+            .parse::<Template>()
+            .unwrap()
+            .render(&todo, &Urgency::default(), now)
+    };
+    assert_eq!(
+        render("{id}\\t{title} [{tags}] {due:relative}"),
+        "7\tBuy groceries [home,work] due in 2d"
+    );
+    assert_eq!(render("{title:truncate(8):upper}|{{x}}"), "BUY GRO…|{x}");
+    assert_eq!(render("{content:default(-)} {priority:pad(2)}|"), "-   |");
+    assert!("{nope}".parse::<Template>().is_err());
+    assert!("{title:shout}".parse::<Template>().is_err());
+    assert!("{title".parse::<Template>().is_err());
+}